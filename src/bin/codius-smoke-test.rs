@@ -20,13 +20,15 @@ fn main() {
     let exec = sandbox::executors::Execv::new(argv.as_slice());
     let mut watcher = PrintWatcher {vfs: vfs::VFS::new()};
     watcher.vfs.mount_filesystem("/", Box::new(vfs::native::NativeFS::new(Path::new("/"))));
-    let mut sbox = sandbox::Sandbox::new(Box::new(exec), Box::new(watcher));
-    sbox.spawn();
+    watcher.vfs.mount_procfs("/proc");
+    watcher.vfs.set_exe(argv[0]);
+    let mut sbox = sandbox::Sandbox::new(Box::new(exec), Box::new(watcher), sandbox::Policy::default());
+    sbox.spawn().ok().expect("Could not spawn sandbox");
     loop {
         if !sbox.is_running() {
             break
         }
-        sbox.tick();
+        sbox.tick().ok().expect("Could not wait for sandbox events");
     }
 }
 
@@ -36,27 +38,82 @@ struct PrintWatcher<'a> {
 
 impl<'a> events::Watcher for PrintWatcher<'a> {
     fn notify_event(&mut self, event: &events::Event) {
-        match event.state {
+        let res = match event.state {
             events::State::Exit(st) => {
                 println!("Child exited with {:?}", st);
-                event.cont();
+                event.cont()
+            },
+            events::State::Killed(s) => {
+                println!("Child was killed by {:?}", s);
+                Ok(())
+            },
+            events::State::TaskExited(st) => {
+                println!("Task {:?} exited with {:?}", event.get_pid(), st);
+                Ok(())
+            },
+            events::State::TaskKilled(s) => {
+                println!("Task {:?} was killed by {:?}", event.get_pid(), s);
+                Ok(())
+            },
+            events::State::ThreadCreated(pid) => {
+                println!("Task {:?} started thread {:?}", event.get_pid(), pid);
+                event.cont()
+            },
+            events::State::ProcessCreated(pid) => {
+                println!("Task {:?} started process {:?}", event.get_pid(), pid);
+                event.cont()
+            },
+            events::State::PTrace(_) => {
+                event.cont()
             },
             events::State::EnteredMain => {
                 println!("Child has entered main()");
-                event.cont();
+                event.cont()
             },
             events::State::Signal(s) => {
                 println!("Got signal {:?}", s);
-                event.cont();
+                event.cont()
+            },
+            events::State::LimitExceeded(limit) => {
+                println!("Task {:?} exceeded its {:?} limit", event.get_pid(), limit);
+                Ok(())
+            },
+            events::State::OutOfMemory => {
+                println!("Task {:?} ran out of memory", event.get_pid());
+                Ok(())
+            },
+            events::State::CgroupUsage(usage) => {
+                println!("Sandbox used {:?}", usage);
+                Ok(())
+            },
+            events::State::CgroupUnavailable(errno) => {
+                println!("Running without a cgroup: errno {:?}", errno);
+                Ok(())
+            },
+            events::State::Timeout(kind) => {
+                println!("Sandbox timed out: {:?}", kind);
+                Ok(())
+            },
+            events::State::Denied(call) => {
+                println!("Denied syscall {:?}", call);
+                event.cont()
             },
             events::State::Seccomp(_) => {
                 let mut e = events::Syscall::from_event(*event).expect("Not a syscall?");
-                (&mut self.vfs as &mut events::SyscallHandler).handle_syscall(&mut e);
+                (&mut self.vfs as &mut events::SyscallHandler).handle_syscall(&mut e)
+            },
+            events::State::Vanished => {
+                println!("Task {:?} vanished", event.get_pid());
+                Ok(())
             },
-            events::State::None => {},
+            events::State::None => Ok(()),
             _ => {
                 panic!("Unhandled sandbox event {:?}", event);
             }
+        };
+        match res {
+            Err(ref err) if !err.is_vanished() => println!("Could not handle {:?}: {:?}", event, err),
+            _ => {}
         }
     }
 }
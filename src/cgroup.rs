@@ -0,0 +1,170 @@
+#[allow(unstable)]
+extern crate libc;
+
+use std::io::{File, Write, Open, IoResult, FilePermission};
+use std::io::fs;
+use std::str::FromStr;
+use vfs::errno;
+
+/// Where and how to create the cgroup v2 group that each sandbox runs in
+#[derive(Show, Clone, PartialEq)]
+pub struct CgroupConfig {
+    /// A cgroup delegated to us, such as one systemd hands out with
+    /// Delegate=yes. Each sandbox gets a group of its own beneath it.
+    pub parent: Path,
+    /// Bytes of memory for the whole process tree, past which the kernel
+    /// OOM kills one of its tasks
+    pub memory_max: Option<u64>,
+    /// Microseconds of CPU time the tree may use per period, and the period
+    /// in microseconds: (50000, 100000) is half a CPU
+    pub cpu_max: Option<(u64, u64)>,
+    /// Tasks the tree may have at once; past it, fork and clone fail with
+    /// EAGAIN
+    pub pids_max: Option<u64>
+}
+
+impl CgroupConfig {
+    pub fn new(parent: Path) -> CgroupConfig {
+        CgroupConfig {
+            parent: parent,
+            memory_max: None,
+            cpu_max: None,
+            pids_max: None
+        }
+    }
+}
+
+/// What a sandbox's process tree has used, as its cgroup counts it. Each
+/// controller's figures are None when it isn't enabled for the group.
+#[derive(Show, Copy, PartialEq)]
+pub struct CgroupUsage {
+    pub memory_bytes: Option<u64>,
+    /// The most memory ever in use, on kernels that track it
+    pub memory_peak: Option<u64>,
+    pub oom_kills: Option<u64>,
+    /// Every cgroup counts CPU time, with or without the cpu controller
+    pub cpu_usec: u64,
+    pub pids: Option<u64>
+}
+
+/// A cgroup created for one sandbox. It is removed when dropped, which only
+/// works once every task in it has exited.
+pub struct Cgroup {
+    path: Path,
+    oom_kills: u64
+}
+
+impl Cgroup {
+    pub fn create(config: &CgroupConfig, name: &str) -> IoResult<Cgroup> {
+        let mut controllers = Vec::new();
+        if config.memory_max.is_some() {
+            controllers.push("+memory");
+        }
+        if config.cpu_max.is_some() {
+            controllers.push("+cpu");
+        }
+        if config.pids_max.is_some() {
+            controllers.push("+pids");
+        }
+        // Fails if they are already enabled or the parent can't have them,
+        // in which case writing the limits below says which
+        if !controllers.is_empty() {
+            let _ = write_file(&config.parent.join("cgroup.subtree_control"), &controllers.connect(" ")[]);
+        }
+
+        let path = config.parent.join(name);
+        try!(fs::mkdir(&path, FilePermission::from_bits_truncate(0o755)));
+        let cgroup = Cgroup {
+            path: path,
+            oom_kills: 0
+        };
+        match config.memory_max {
+            Some(max) => try!(cgroup.write("memory.max", &format!("{}", max)[])),
+            None => {}
+        }
+        match config.cpu_max {
+            Some((quota, period)) => try!(cgroup.write("cpu.max", &format!("{} {}", quota, period)[])),
+            None => {}
+        }
+        match config.pids_max {
+            Some(max) => try!(cgroup.write("pids.max", &format!("{}", max)[])),
+            None => {}
+        }
+        Ok(cgroup)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves a process into the cgroup. Tasks it starts afterwards are
+    /// placed there too.
+    pub fn add(&self, pid: libc::pid_t) -> IoResult<()> {
+        self.write("cgroup.procs", &format!("{}", pid)[])
+    }
+
+    pub fn usage(&self) -> IoResult<CgroupUsage> {
+        let cpu_usec = try!(self.read_key("cpu.stat", "usage_usec"));
+        Ok(CgroupUsage {
+            memory_bytes: self.read_u64("memory.current").ok(),
+            memory_peak: self.read_u64("memory.peak").ok(),
+            oom_kills: self.read_key("memory.events", "oom_kill").ok(),
+            cpu_usec: cpu_usec,
+            pids: self.read_u64("pids.current").ok()
+        })
+    }
+
+    /// Whether the kernel has OOM killed a task in the cgroup since the last
+    /// time this was asked
+    pub fn check_oom_kill(&mut self) -> bool {
+        match self.read_key("memory.events", "oom_kill") {
+            Ok(n) if n > self.oom_kills => {
+                self.oom_kills = n;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn write(&self, file: &str, value: &str) -> IoResult<()> {
+        write_file(&self.path.join(file), value)
+    }
+
+    fn read(&self, file: &str) -> IoResult<String> {
+        File::open(&self.path.join(file)).and_then(|mut f| f.read_to_string())
+    }
+
+    fn read_u64(&self, file: &str) -> IoResult<u64> {
+        let text = try!(self.read(file));
+        Ok(FromStr::from_str(text.trim()).unwrap_or(0))
+    }
+
+    /// Reads one value from a flat keyed file such as cpu.stat
+    fn read_key(&self, file: &str, key: &str) -> IoResult<u64> {
+        let text = try!(self.read(file));
+        for line in text.lines() {
+            let mut parts = line.splitn(1, ' ');
+            if parts.next() == Some(key) {
+                return Ok(parts.next().and_then(|v| FromStr::from_str(v.trim())).unwrap_or(0));
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = fs::rmdir(&self.path);
+    }
+}
+
+/// Writes to a control file the kernel made. Opening for writing would
+/// create a missing file, which would make a plain directory pass for a
+/// cgroup.
+fn write_file(path: &Path, value: &str) -> IoResult<()> {
+    if !path.exists() {
+        return Err(errno::error(libc::ENOENT));
+    }
+    let mut f = try!(File::open_mode(path, Open, Write));
+    f.write_str(value)
+}
@@ -0,0 +1,35 @@
+#[allow(unstable)]
+extern crate libc;
+
+/// Why an operation on a sandbox or one of its tasks failed
+#[derive(Show, Copy, PartialEq)]
+pub enum SandboxError {
+    /// fork() failed with this errno
+    Fork(usize),
+    /// A ptrace request failed with this errno
+    Ptrace(usize),
+    /// waitpid() failed with this errno
+    Wait(usize),
+    /// waitpid() reported a status that couldn't be decoded
+    UnknownWaitStatus(i32),
+    /// A string argument to a syscall wasn't valid UTF-8
+    InvalidString,
+    /// The child couldn't apply its resource limits, failing with this errno
+    Limits(usize),
+    /// The child couldn't get ready to be traced, failing with this errno
+    Setup(usize)
+}
+
+pub type SandboxResult<T> = Result<T, SandboxError>;
+
+impl SandboxError {
+    /// Whether the error only means that the task concerned has already
+    /// died, which happens whenever a task exits while being traced. Its
+    /// death is still reported as an event of its own.
+    pub fn is_vanished(&self) -> bool {
+        match *self {
+            SandboxError::Ptrace(errno) => errno == libc::ESRCH as usize,
+            _ => false
+        }
+    }
+}
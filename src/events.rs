@@ -5,8 +5,17 @@ extern crate ptrace;
 extern crate seccomp;
 
 use std::num::FromPrimitive;
+use std::mem;
+use std::cmp;
 
 use waitpid;
+use limits;
+use cgroup;
+use error::{SandboxError, SandboxResult};
+
+/// The most bytes a single read or write is copied through the sandbox.
+/// Larger requests are cut short, which POSIX allows.
+pub const MAX_TRANSFER: usize = 64 * 1024;
 
 #[derive(Show, Copy)]
 pub enum State {
@@ -14,10 +23,41 @@ pub enum State {
     Trap,
     Signal(ipc::signals::Signal),
     Exit(isize),
+    Killed(ipc::signals::Signal),
+    TaskExited(isize),
+    TaskKilled(ipc::signals::Signal),
+    ThreadCreated(libc::pid_t),
+    ProcessCreated(libc::pid_t),
     PTrace(ptrace::Event),
     EnteredMain,
     Released(ipc::signals::Signal),
-    Seccomp(ptrace::Syscall)
+    Seccomp(ptrace::Syscall),
+    /// The policy's Errno default refused this call. The task stays stopped
+    /// until resumed with cont, which makes the call return the errno.
+    Denied(ptrace::Syscall),
+    /// The task was sent a signal for running into a resource limit
+    LimitExceeded(limits::Limit),
+    /// The kernel OOM killed the task for using more memory than the
+    /// sandbox's cgroup allows. Its death is reported next.
+    OutOfMemory,
+    /// What the sandbox's cgroup used, reported just before the sandbox exits
+    CgroupUsage(cgroup::CgroupUsage),
+    /// The sandbox couldn't be given a cgroup, with this errno, and is
+    /// running without one
+    CgroupUnavailable(libc::c_int),
+    /// The sandbox ran out of time and is being killed, or signalled and
+    /// given a grace period first
+    Timeout(TimeoutKind),
+    /// The task died before its last event could be handled. How it died
+    /// is reported by a later event.
+    Vanished
+}
+
+/// Which of a sandbox's time limits ran out
+#[derive(Show, Copy, PartialEq)]
+pub enum TimeoutKind {
+    Deadline,
+    Idle
 }
 
 #[derive(Show, Copy)]
@@ -35,52 +75,103 @@ pub struct Syscall {
 }
 
 impl Syscall {
+    /// None if the event isn't a syscall, or is one seccomp doesn't know
     pub fn from_event(event: Event) -> Option<Syscall> {
         match event.state {
-            State::Seccomp(call) => Option::Some(
+            State::Seccomp(call) => FromPrimitive::from_u64(call.call).map(|symbolic| {
                 Syscall {
                     pid: event.pid,
                     call: call,
                     finished: false,
-                    symbolic: FromPrimitive::from_u64(call.call).expect("Unknown syscall")
+                    symbolic: symbolic
                 }
-            ),
+            }),
             _ => Option::None
         }
     }
 
-    pub fn finish(&mut self, return_val: ptrace::Word) {
+    pub fn finish(&mut self, return_val: ptrace::Word) -> SandboxResult<()> {
         assert!(!self.finished);
         self.call.call = -1;
         self.call.returnVal = return_val;
         self.finished = true;
-        self.call.write().ok().expect("Could not write registers");
-        ptrace::cont(self.pid, ipc::signals::Signal::None).ok().expect("Could not continue child");
+        try!(self.call.write().map_err(SandboxError::Ptrace));
+        ptrace::cont(self.pid, ipc::signals::Signal::None).map(|_| ()).map_err(SandboxError::Ptrace)
     }
 
-    pub fn finish_default(&mut self) {
+    pub fn finish_default(&mut self) -> SandboxResult<()> {
         assert!(!self.finished);
         self.finished = true;
-        self.call.write().ok().expect("Could not write registers");
-        ptrace::cont(self.pid, ipc::signals::Signal::None).ok().expect("Could not continue child");
+        try!(self.call.write().map_err(SandboxError::Ptrace));
+        ptrace::cont(self.pid, ipc::signals::Signal::None).map(|_| ()).map_err(SandboxError::Ptrace)
     }
 
-    pub fn kill(&mut self) {
+    pub fn kill(&mut self) -> SandboxResult<()> {
         assert!(!self.finished);
         self.finished = true;
-        ptrace::cont(self.pid, ipc::signals::Signal::Kill).ok().expect("Could not continue child");
+        ptrace::cont(self.pid, ipc::signals::Signal::Kill).map(|_| ()).map_err(SandboxError::Ptrace)
     }
 
-    pub fn read_string_arg(&self, arg_num: usize) -> String {
+    pub fn read_string_arg(&self, arg_num: usize) -> SandboxResult<String> {
         let reader = ptrace::Reader::new(self.pid);
         let s = reader.read_string(self.call.args[arg_num]);
-        return String::from_utf8(s).ok().expect("Could not read string argument");
+        String::from_utf8(s).map_err(|_| SandboxError::InvalidString)
     }
 
     pub fn write_buf_arg<T: Sized>(&self, arg_num: usize, buf: &T) -> Result<(), usize> {
         let writer = ptrace::Writer::new(self.pid);
         writer.write_object (self.call.args[arg_num], buf)
     }
+
+    /// Copies len bytes out of the tracee's memory, starting at addr. Fails
+    /// with EINVAL past MAX_TRANSFER, so callers clamp tracee-given lengths
+    /// first.
+    pub fn read_bytes(&self, addr: ptrace::Address, len: usize) -> Result<Vec<u8>, usize> {
+        if len > MAX_TRANSFER {
+            return Err(libc::EINVAL as usize);
+        }
+        let reader = ptrace::Reader::new(self.pid);
+        let word_size = mem::size_of::<ptrace::Word>();
+        let mut buf = Vec::with_capacity(len);
+        let mut offset = 0;
+        while offset < len {
+            let word = try!(reader.peek_data(addr + offset as u64));
+            for i in range(0, word_size) {
+                if offset + i >= len {
+                    break;
+                }
+                buf.push(((word >> (i * 8)) & 0xff) as u8);
+            }
+            offset += word_size;
+        }
+        Ok(buf)
+    }
+
+    /// Copies buf into the tracee's memory, starting at addr.
+    ///
+    /// A trailing partial word is merged with the tracee's existing memory
+    /// so that nothing past the end of buf is clobbered.
+    pub fn write_bytes(&self, addr: ptrace::Address, buf: &[u8]) -> Result<(), usize> {
+        let reader = ptrace::Reader::new(self.pid);
+        let writer = ptrace::Writer::new(self.pid);
+        let word_size = mem::size_of::<ptrace::Word>();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let remaining = buf.len() - offset;
+            let mut word: ptrace::Word = if remaining < word_size {
+                try!(reader.peek_data(addr + offset as u64))
+            } else {
+                0
+            };
+            for i in range(0, cmp::min(remaining, word_size)) {
+                word &= !(0xff << (i * 8));
+                word |= (buf[offset + i] as ptrace::Word) << (i * 8);
+            }
+            try!(writer.poke_data(addr + offset as u64, word));
+            offset += word_size;
+        }
+        Ok(())
+    }
 }
 
 impl Event {
@@ -91,15 +182,28 @@ impl Event {
         }
     }
 
-    pub fn cont(&self) {
-        match self.state {
-            State::Signal(sig) => ptrace::cont(self.pid, sig).ok().expect("Could not pass signal through to child"),
-            _ => ptrace::cont(self.pid, ipc::signals::Signal::None).ok().expect("Could not continue child")
+    /// An event that didn't come from waiting on a task
+    pub fn for_pid(pid: libc::pid_t, event_state: State) -> Self {
+        Event {
+            pid: pid,
+            state: event_state,
+        }
+    }
+
+    pub fn get_pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    pub fn cont(&self) -> SandboxResult<()> {
+        let sig = match self.state {
+            State::Signal(sig) => sig,
+            _ => ipc::signals::Signal::None
         };
+        ptrace::cont(self.pid, sig).map(|_| ()).map_err(SandboxError::Ptrace)
     }
 
-    pub fn kill(&self) {
-        ptrace::cont(self.pid, ipc::signals::Signal::Kill).ok().expect("Could not kill child");
+    pub fn kill(&self) -> SandboxResult<()> {
+        ptrace::cont(self.pid, ipc::signals::Signal::Kill).map(|_| ()).map_err(SandboxError::Ptrace)
     }
 }
 
@@ -126,5 +230,7 @@ pub trait Watcher {
 }
 
 pub trait SyscallHandler {
-    fn handle_syscall(&mut self, call: &mut Syscall);
+    /// Handles call, finishing it one way or another. Errors are from
+    /// finishing it, most often because the task has vanished.
+    fn handle_syscall(&mut self, call: &mut Syscall) -> SandboxResult<()>;
 }
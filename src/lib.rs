@@ -1,8 +1,18 @@
 pub use sandbox::Sandbox;
+pub use policy::Policy;
+pub use error::{SandboxError, SandboxResult};
 pub mod sandbox;
 pub mod executors;
 pub mod events;
 pub mod vfs;
 pub mod io;
+pub mod policy;
+pub mod tasks;
+pub mod error;
+pub mod limits;
+pub mod cgroup;
+#[cfg(feature = "stream")]
+pub mod stream;
 
 mod waitpid;
+mod signalfd;
@@ -0,0 +1,115 @@
+#[allow(unstable)]
+extern crate libc;
+extern crate "posix-ipc" as ipc;
+
+use std::os;
+
+/// Resource limits applied to the sandboxed process before it execs, and so
+/// to every task it starts. None leaves a limit as the host's.
+#[derive(Show, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    /// Seconds of CPU time. The process gets SIGXCPU once it runs out, and
+    /// SIGKILL a second later.
+    pub cpu_seconds: Option<u64>,
+    /// Bytes of virtual memory; past it, allocations fail with ENOMEM
+    pub address_space: Option<u64>,
+    /// Open file descriptors; past it, opens fail with EMFILE
+    pub open_files: Option<u64>,
+    /// Processes owned by the sandbox's user, including ones outside the
+    /// sandbox; past it, fork and clone fail with EAGAIN
+    pub processes: Option<u64>,
+    /// Bytes any one file can be written to; past it, writes get SIGXFSZ
+    pub file_size: Option<u64>,
+    /// Bytes of core dump; zero disables them
+    pub core_size: Option<u64>
+}
+
+/// A limit that a task ran into
+#[derive(Show, Copy, PartialEq)]
+pub enum Limit {
+    Cpu,
+    FileSize
+}
+
+impl Limit {
+    /// The limit that the kernel signals with sig, if any
+    pub fn from_signal(sig: ipc::signals::Signal) -> Option<Limit> {
+        match sig {
+            ipc::signals::Signal::Xcpu => Some(Limit::Cpu),
+            ipc::signals::Signal::Xfsz => Some(Limit::FileSize),
+            _ => None
+        }
+    }
+
+    /// The limit that a task in process tgid ran into, if the kernel sent
+    /// it sig for one rather than another task. code and sender are the
+    /// signal's si_code and si_pid.
+    pub fn from_siginfo(sig: ipc::signals::Signal, code: libc::c_int, sender: libc::pid_t, tgid: libc::pid_t) -> Option<Limit> {
+        match Limit::from_signal(sig) {
+            Some(Limit::Cpu) if code == SI_KERNEL => Some(Limit::Cpu),
+            // The kernel sends SIGXFSZ as though the writer had raised it
+            Some(Limit::FileSize) if code == SI_USER && sender == tgid => Some(Limit::FileSize),
+            _ => None
+        }
+    }
+}
+
+impl Limits {
+    /// Applies the limits to the calling process, returning the errno of
+    /// the first one that couldn't be set
+    pub fn apply(&self) -> Result<(), usize> {
+        // The hard CPU limit is a second past the soft one, so that the
+        // process sees SIGXCPU before it is killed
+        match self.cpu_seconds {
+            Some(secs) => try!(set(RLIMIT_CPU, secs, secs.saturating_add(1))),
+            None => {}
+        }
+        let rest = [
+            (RLIMIT_AS, self.address_space),
+            (RLIMIT_NOFILE, self.open_files),
+            (RLIMIT_NPROC, self.processes),
+            (RLIMIT_FSIZE, self.file_size),
+            (RLIMIT_CORE, self.core_size)
+        ];
+        for &(resource, limit) in rest.iter() {
+            match limit {
+                Some(v) => try!(set(resource, v, v)),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set(resource: libc::c_int, soft: u64, hard: u64) -> Result<(), usize> {
+    let lim = RLimit {cur: soft, max: hard};
+    if unsafe { ext::setrlimit(resource, &lim) } < 0 {
+        Err(os::errno())
+    } else {
+        Ok(())
+    }
+}
+
+#[repr(C)]
+struct RLimit {
+    cur: u64,
+    max: u64
+}
+
+const SI_USER: libc::c_int = 0;
+const SI_KERNEL: libc::c_int = 0x80;
+
+const RLIMIT_CPU: libc::c_int = 0;
+const RLIMIT_FSIZE: libc::c_int = 1;
+const RLIMIT_CORE: libc::c_int = 4;
+const RLIMIT_NPROC: libc::c_int = 6;
+const RLIMIT_NOFILE: libc::c_int = 7;
+const RLIMIT_AS: libc::c_int = 9;
+
+mod ext {
+    use super::libc;
+    use super::RLimit;
+    extern "C" {
+        pub fn setrlimit(resource: libc::c_int, rlim: *const RLimit) -> libc::c_int;
+    }
+}
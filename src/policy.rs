@@ -0,0 +1,257 @@
+extern crate seccomp;
+
+use self::seccomp::Syscall;
+use vfs;
+
+/// Data attached to SECCOMP_RET_TRACE by rules that want the VFS or another
+/// handler to service the call.
+pub const TRACE_HANDLE: u32 = 0;
+
+/// Data attached to SECCOMP_RET_TRACE when a call fell through to an Errno
+/// default action, so that the sandbox can report it before failing it.
+pub const TRACE_DENIED: u32 = 1;
+
+/// What the seccomp filter should do when a syscall matches a rule
+#[derive(Show, Copy, Clone, PartialEq)]
+pub enum Action {
+    Allow,
+    Trace,
+    Kill,
+    Errno(u32)
+}
+
+impl Action {
+    fn to_seccomp(&self) -> seccomp::Action {
+        match *self {
+            Action::Allow => seccomp::ACT_ALLOW,
+            Action::Trace => seccomp::act_trace(TRACE_HANDLE),
+            Action::Kill => seccomp::ACT_KILL,
+            Action::Errno(e) => seccomp::act_errno(e)
+        }
+    }
+}
+
+/// A comparison against one of the syscall's arguments, equivalent to
+/// seccomp::Compare but copyable so that policies can be built ahead of
+/// time and applied in the child.
+#[derive(Show, Copy, Clone)]
+pub struct Compare {
+    pub arg: u32,
+    pub op: seccomp::Op,
+    pub value: u64
+}
+
+impl Compare {
+    pub fn new(arg: u32, op: seccomp::Op, value: u64) -> Compare {
+        Compare {
+            arg: arg,
+            op: op,
+            value: value
+        }
+    }
+
+    fn to_seccomp(&self) -> seccomp::Compare {
+        seccomp::Compare::new(self.arg, self.op, self.value)
+    }
+}
+
+#[derive(Show, Clone)]
+pub struct Rule {
+    pub call: Syscall,
+    pub action: Action,
+    pub args: Vec<Compare>
+}
+
+/// A declarative list of seccomp rules that is loaded into the sandboxed
+/// child right before it execs.
+#[derive(Show, Clone)]
+pub struct Policy {
+    default: Action,
+    rules: Vec<Rule>
+}
+
+impl Policy {
+    /// Creates an empty policy that kills the child on any syscall
+    pub fn new() -> Policy {
+        Policy {
+            default: Action::Kill,
+            rules: Vec::new()
+        }
+    }
+
+    pub fn get_default(&self) -> Action {
+        self.default
+    }
+
+    /// Sets the action taken for syscalls that don't match any rule.
+    ///
+    /// With Action::Errno, unlisted calls fail with the given errno instead
+    /// of killing the child, and each one is reported to the sandbox's
+    /// watcher as events::State::Denied. The task stays stopped until the
+    /// watcher resumes it with Event::cont, and only then sees the errno.
+    pub fn set_default(&mut self, action: Action) -> &mut Policy {
+        self.default = action;
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules[]
+    }
+
+    pub fn add_rule(&mut self, action: Action, call: Syscall, args: &[Compare]) -> &mut Policy {
+        self.rules.push(Rule {
+            call: call,
+            action: action,
+            args: args.to_vec()
+        });
+        self
+    }
+
+    pub fn allow(&mut self, call: Syscall) -> &mut Policy {
+        self.add_rule(Action::Allow, call, &[])
+    }
+
+    pub fn trace(&mut self, call: Syscall) -> &mut Policy {
+        self.add_rule(Action::Trace, call, &[])
+    }
+
+    pub fn kill(&mut self, call: Syscall) -> &mut Policy {
+        self.add_rule(Action::Kill, call, &[])
+    }
+
+    pub fn errno(&mut self, call: Syscall, errno: u32) -> &mut Policy {
+        self.add_rule(Action::Errno(errno), call, &[])
+    }
+
+    /// Traces calls whose first argument is a virtual file descriptor, and
+    /// allows the rest through to the kernel.
+    pub fn trace_virtual_fd(&mut self, call: Syscall) -> &mut Policy {
+        self.add_rule(Action::Trace, call, &[
+            Compare::new(0, seccomp::Op::OpGe, vfs::FIRST_VIRTUAL_FD as u64)
+        ]);
+        self.add_rule(Action::Allow, call, &[
+            Compare::new(0, seccomp::Op::OpLt, vfs::FIRST_VIRTUAL_FD as u64)
+        ])
+    }
+
+    #[allow(unused_must_use)]
+    pub fn to_filter(&self) -> seccomp::Filter {
+        let default = match self.default {
+            Action::Errno(_) => seccomp::act_trace(TRACE_DENIED),
+            a => a.to_seccomp()
+        };
+        let filter = seccomp::Filter::new(&default).ok().expect("Could not allocate seccomp filter");
+
+        // A duplicate in case the default action is accidentally modified
+        filter.rule_add(&seccomp::ACT_KILL, Syscall::PTRACE, &[]);
+
+        for rule in self.rules.iter() {
+            let args: Vec<seccomp::Compare> = rule.args.iter().map(|c| c.to_seccomp()).collect();
+            filter.rule_add(&rule.action.to_seccomp(), rule.call, &args[]);
+        }
+
+        filter
+    }
+}
+
+impl Default for Policy {
+    /// The set of rules needed to run a typical dynamically linked binary
+    /// with its filesystem access routed through the VFS layer.
+    fn default() -> Policy {
+        let mut p = Policy::new();
+
+        // This is actually caught via PTRACE_EVENT_EXEC
+        p.allow(Syscall::EXECVE);
+        p.allow(Syscall::CLONE);
+
+        // Use to track chdir calls
+        p.trace(Syscall::CHDIR);
+        p.trace(Syscall::FCHDIR);
+
+        // These interact with the VFS layer
+        p.trace(Syscall::OPEN);
+        p.trace(Syscall::ACCESS);
+        p.trace(Syscall::OPENAT);
+        p.trace(Syscall::STAT);
+        p.trace(Syscall::LSTAT);
+        p.trace(Syscall::GETCWD);
+        p.trace(Syscall::READLINK);
+        p.trace(Syscall::READLINKAT);
+        p.trace(Syscall::NEWFSTATAT);
+        p.trace(Syscall::FACCESSAT);
+        p.trace(Syscall::UNLINK);
+        p.trace(Syscall::UNLINKAT);
+        p.trace(Syscall::RMDIR);
+        p.trace(Syscall::MKDIR);
+        p.trace(Syscall::MKDIRAT);
+        p.trace(Syscall::RENAME);
+        p.trace(Syscall::RENAMEAT);
+        p.trace(Syscall::LINK);
+        p.trace(Syscall::SYMLINK);
+        p.trace(Syscall::CHMOD);
+        p.trace(Syscall::UMASK);
+        p.trace(Syscall::TRUNCATE);
+        p.trace(Syscall::UTIMES);
+
+        p.trace_virtual_fd(Syscall::READ);
+        p.trace_virtual_fd(Syscall::CLOSE);
+        p.trace_virtual_fd(Syscall::IOCTL);
+        p.trace_virtual_fd(Syscall::FSTAT);
+        p.trace_virtual_fd(Syscall::LSEEK);
+        p.trace_virtual_fd(Syscall::WRITE);
+        p.trace_virtual_fd(Syscall::GETDENTS);
+        //p.trace_virtual_fd(Syscall::READDIR);
+        p.trace_virtual_fd(Syscall::GETDENTS64);
+        p.trace_virtual_fd(Syscall::READV);
+        p.trace_virtual_fd(Syscall::WRITEV);
+        p.trace_virtual_fd(Syscall::FTRUNCATE);
+        p.trace_virtual_fd(Syscall::FSYNC);
+        p.trace_virtual_fd(Syscall::FDATASYNC);
+
+        p.allow(Syscall::SYNC);
+        p.allow(Syscall::POLL);
+        p.allow(Syscall::MMAP);
+        p.allow(Syscall::MPROTECT);
+        p.allow(Syscall::MUNMAP);
+        p.allow(Syscall::MADVISE);
+        p.allow(Syscall::BRK);
+        p.allow(Syscall::RT_SIGACTION);
+        p.allow(Syscall::RT_SIGPROCMASK);
+        p.allow(Syscall::SELECT);
+        p.allow(Syscall::SCHED_YIELD);
+        p.allow(Syscall::GETPID);
+        p.allow(Syscall::ACCEPT);
+        p.allow(Syscall::LISTEN);
+        p.allow(Syscall::EXIT);
+        p.allow(Syscall::GETTIMEOFDAY);
+        p.allow(Syscall::TKILL);
+        p.allow(Syscall::EPOLL_CREATE);
+        p.allow(Syscall::RESTART_SYSCALL);
+        p.allow(Syscall::CLOCK_GETTIME);
+        p.allow(Syscall::CLOCK_GETRES);
+        p.allow(Syscall::CLOCK_NANOSLEEP);
+        p.allow(Syscall::GETTID);
+        p.allow(Syscall::IOCTL);
+        p.allow(Syscall::NANOSLEEP);
+        p.allow(Syscall::EXIT_GROUP);
+        p.allow(Syscall::EPOLL_WAIT);
+        p.allow(Syscall::EPOLL_CTL);
+        p.allow(Syscall::TGKILL);
+        p.allow(Syscall::PSELECT6);
+        p.allow(Syscall::PPOLL);
+        p.allow(Syscall::ARCH_PRCTL);
+        p.allow(Syscall::PRCTL);
+        p.allow(Syscall::SET_ROBUST_LIST);
+        p.allow(Syscall::GET_ROBUST_LIST);
+        p.allow(Syscall::EPOLL_PWAIT);
+        p.allow(Syscall::ACCEPT4);
+        p.allow(Syscall::EVENTFD2);
+        p.allow(Syscall::EPOLL_CREATE1);
+        p.allow(Syscall::PIPE2);
+        p.allow(Syscall::FUTEX);
+        p.allow(Syscall::SET_TID_ADDRESS);
+        p.allow(Syscall::SET_THREAD_AREA);
+
+        p
+    }
+}
@@ -1,221 +1,734 @@
 #[allow(unstable)]
 extern crate libc;
 extern crate ptrace;
-extern crate seccomp;
 extern crate "posix-ipc" as ipc;
 
 use executors::Executor;
 use waitpid;
 use events;
+use policy;
+use policy::Policy;
+use tasks;
+use signalfd;
+use limits::{Limits, Limit};
+use cgroup::{Cgroup, CgroupConfig, CgroupUsage};
+use vfs::errno;
+use error::{SandboxError, SandboxResult};
+use std::collections::HashSet;
+use std::os;
+use std::cmp;
+use std::i32;
+use std::time::Duration;
+use std::io::timer;
 
 pub struct Sandbox<'a, 'b> {
     pid: libc::pid_t,
+    policy: Policy,
     executor: Box<Executor + 'a>,
     entered_main: bool,
     event_watch: Box<events::Watcher + 'b>,
-    running: bool
+    running: bool,
+    tasks: tasks::TaskTable,
+    early_stops: HashSet<libc::pid_t>,
+    exit_status: Option<isize>,
+    limits: Limits,
+    cgroup_config: Option<CgroupConfig>,
+    cgroup: Option<Cgroup>,
+    pending: Vec<events::Event>,
+    deadline_ms: Option<u64>,
+    idle_ms: Option<u64>,
+    grace: Option<(ipc::signals::Signal, u64)>,
+    started_at: u64,
+    last_activity: u64,
+    timed_out: bool,
+    kill_at: Option<u64>,
+    wants_event_fd: bool,
+    child_events: Option<signalfd::SignalFd>
 }
 
 impl<'a, 'b> Sandbox<'a, 'b> {
-    pub fn new(exec: Box<Executor + 'a>, watcher: Box<events::Watcher + 'b>) -> Sandbox<'a, 'b> {
+    pub fn new(exec: Box<Executor + 'a>, watcher: Box<events::Watcher + 'b>, policy: Policy) -> Sandbox<'a, 'b> {
         Sandbox {
             pid: -1,
+            policy: policy,
             executor: exec,
             entered_main: false,
             event_watch: watcher,
-            running: true
+            running: true,
+            tasks: tasks::TaskTable::new(),
+            early_stops: HashSet::new(),
+            exit_status: None,
+            limits: Default::default(),
+            cgroup_config: None,
+            cgroup: None,
+            pending: Vec::new(),
+            deadline_ms: None,
+            idle_ms: None,
+            grace: None,
+            started_at: 0,
+            last_activity: 0,
+            timed_out: false,
+            kill_at: None,
+            wants_event_fd: false,
+            child_events: None
         }
     }
 
-    fn exec_child(&mut self) {
+    /// Runs in the forked child. status is the write end of a pipe that
+    /// the parent reads which step failed and its errno from, or
+    /// STATUS_READY if none did.
+    ///
+    /// Once stopped for the parent to attach, the child has no one to tell,
+    /// so if raising the stop or loading the filter fails it exits with
+    /// status 127 as if exec had failed.
+    fn exec_child(&mut self, status: libc::c_int) -> ! {
         extern "C" { fn clearenv(); fn setpgid(a: libc::c_int, b: libc::c_int); };
         unsafe {
             clearenv(); 
             setpgid(0, 0);
         }
-        ipc::signals::Signal::Stop.raise().ok().expect("Could not stop child");
-        self.setup_seccomp();
+        let report = match signalfd::unblock(&[ipc::signals::Signal::Chld as libc::c_int]) {
+            Err(errno) => [STATUS_SETUP, errno as i32],
+            Ok(()) => match self.limits.apply() {
+                Err(errno) => [STATUS_LIMITS, errno as i32],
+                Ok(()) => [STATUS_READY, 0]
+            }
+        };
+        unsafe {
+            write(status, report.as_ptr() as *const libc::c_void, 8);
+            close(status);
+            if report[0] != STATUS_READY {
+                _exit(127);
+            }
+        }
+        if ipc::signals::Signal::Stop.raise().is_err() || self.setup_seccomp().is_err() {
+            unsafe { _exit(127); }
+        }
         self.executor.exec();
     }
 
-    #[allow(unused_must_use)]
-    fn setup_seccomp(&self) {
-        let filter = seccomp::Filter::new(&seccomp::ACT_KILL).ok().expect("Could not allocate seccomp filter");
-        let trace = seccomp::act_trace(0);
-
-        // A duplicate in case the seccomp_init() call is accidentally modified
-        filter.rule_add(&seccomp::ACT_KILL, seccomp::Syscall::PTRACE, &[]);
-
-        // This is actually caught via PTRACE_EVENT_EXEC
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EXECVE, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::CLONE, &[]);
-
-        // Use to track chdir calls
-        filter.rule_add(&trace, seccomp::Syscall::CHDIR, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::FCHDIR, &[]);
-
-        // These interact with the VFS layer
-        filter.rule_add(&trace, seccomp::Syscall::OPEN, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::ACCESS, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::OPENAT, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::STAT, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::LSTAT, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::GETCWD, &[]);
-        filter.rule_add(&trace, seccomp::Syscall::READLINK, &[]);
-
-        macro_rules! vfs_filter(
-          ($call:ident) => ({
-            filter.rule_add(&trace, seccomp::Syscall::$call, &[
-              seccomp::Compare::new(0, seccomp::Op::OpGe, 4098)
-            ]);
-            filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::$call, &[
-              seccomp::Compare::new(0, seccomp::Op::OpLt, 4098)
-            ]);
-          });
-        );
-
-
-        vfs_filter!(READ);
-        vfs_filter!(CLOSE);
-        vfs_filter!(IOCTL);
-        vfs_filter!(FSTAT);
-        vfs_filter!(LSEEK);
-        vfs_filter!(WRITE);
-        vfs_filter!(GETDENTS);
-        //vfs_filter!(READDIR);
-        vfs_filter!(GETDENTS64);
-        vfs_filter!(READV);
-        vfs_filter!(WRITEV);
-
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::FSYNC, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::FDATASYNC, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::SYNC, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::POLL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::MMAP, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::MPROTECT, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::MUNMAP, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::MADVISE, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::BRK, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::RT_SIGACTION, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::RT_SIGPROCMASK, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::SELECT, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::SCHED_YIELD, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::GETPID, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::ACCEPT, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::LISTEN, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EXIT, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::GETTIMEOFDAY, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::TKILL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EPOLL_CREATE, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::RESTART_SYSCALL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::CLOCK_GETTIME, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::CLOCK_GETRES, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::CLOCK_NANOSLEEP, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::GETTID, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::IOCTL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::NANOSLEEP, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EXIT_GROUP, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EPOLL_WAIT, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EPOLL_CTL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::TGKILL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::PSELECT6, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::PPOLL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::ARCH_PRCTL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::PRCTL, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::SET_ROBUST_LIST, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::GET_ROBUST_LIST, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EPOLL_PWAIT, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::ACCEPT4, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EVENTFD2, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::EPOLL_CREATE1, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::PIPE2, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::FUTEX, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::SET_TID_ADDRESS, &[]);
-        filter.rule_add(&seccomp::ACT_ALLOW, seccomp::Syscall::SET_THREAD_AREA, &[]);
-
-        filter.load().ok().expect("Could not load filter");
-    }
-
-    fn attach_to_child(&self) {
-        ptrace::attach(self.pid).ok().expect("Could not attach.");
+    /// Loads the policy's filter, returning the errno if it can't be
+    fn setup_seccomp(&self) -> Result<(), usize> {
+        let filter = self.policy.to_filter();
+        filter.load().map_err(|_| os::errno())
+    }
+
+    fn attach_to_child(&mut self) -> SandboxResult<()> {
+        try!(ptrace::attach(self.pid).map_err(SandboxError::Ptrace));
         let s = waitpid::wait(self.pid, waitpid::None);
         println!("post attach: {:?}", s);
-        s.ok().expect("Could not wait for child to enter ptrace");
-        ptrace::setoptions(self.pid,
-                           ptrace::TraceExit | ptrace::ExitKill |
-                           ptrace::TraceSeccomp | ptrace::TraceExec |
-                           ptrace::TraceClone).ok().expect("Could not set options");
-        ptrace::cont(self.pid, ipc::signals::Signal::None).ok().expect("Could not continue");
+        try!(s);
+        try!(ptrace::setoptions(self.pid,
+                                ptrace::TraceExit | ptrace::ExitKill |
+                                ptrace::TraceSeccomp | ptrace::TraceExec |
+                                ptrace::TraceClone | ptrace::TraceFork |
+                                ptrace::TraceVFork).map_err(SandboxError::Ptrace));
+        self.tasks.insert_root(self.pid);
+        cont(self.pid, ipc::signals::Signal::None)
+    }
+
+    fn handle_new_task(&mut self, res: waitpid::WaitResult, e: ptrace::Event) -> SandboxResult<events::Event> {
+        let child = try!(get_event_msg(res.pid).map_err(SandboxError::Ptrace)) as libc::pid_t;
+        let kind = match e {
+            ptrace::Event::Clone => {
+                let call = ptrace::Syscall::from_pid(res.pid);
+                if call.args[0] & CLONE_THREAD != 0 {
+                    tasks::Kind::Thread
+                } else {
+                    tasks::Kind::Process
+                }
+            },
+            _ => tasks::Kind::Process
+        };
+        self.tasks.insert(child, res.pid, kind);
+        self.kill_if_timed_out(child);
+
+        // The new task's initial SIGSTOP can race ahead of this event
+        if self.early_stops.remove(&child) {
+            self.tasks.start(child);
+            try!(ignore_vanished(cont(child, ipc::signals::Signal::None)));
+        }
+
+        Ok(match kind {
+            tasks::Kind::Thread => events::Event::new(res, events::State::ThreadCreated(child)),
+            tasks::Kind::Process => events::Event::new(res, events::State::ProcessCreated(child))
+        })
+    }
+
+    fn handle_stop(&mut self, res: waitpid::WaitResult, s: ipc::signals::Signal) -> SandboxResult<events::Event> {
+        if !self.tasks.contains(res.pid) {
+            self.early_stops.insert(res.pid);
+            self.kill_if_timed_out(res.pid);
+            return Ok(events::Event::new(res, events::State::None));
+        }
+        if self.tasks.start(res.pid) {
+            try!(cont(res.pid, ipc::signals::Signal::None));
+            return Ok(events::Event::new(res, events::State::None));
+        }
+        // Anyone can send the signals a limit is enforced with, so only
+        // ones from the kernel count
+        let limit = match Limit::from_signal(s) {
+            Some(_) => {
+                let info = try!(get_siginfo(res.pid).map_err(SandboxError::Ptrace));
+                Limit::from_siginfo(s, info.code, info.pid, self.tasks.process_of(res.pid))
+            },
+            None => None
+        };
+        try!(cont(res.pid, s));
+        Ok(match limit {
+            Some(limit) => events::Event::new(res, events::State::LimitExceeded(limit)),
+            None => events::Event::new(res, events::State::Signal(s))
+        })
+    }
+
+    fn handle_task_end(&mut self, res: waitpid::WaitResult) -> events::Event {
+        let task = self.tasks.remove(res.pid);
+        let is_root = match task {
+            Some(t) => t.parent == -1,
+            None => false
+        };
+
+        if is_root {
+            self.exit_status = match res.state {
+                waitpid::WaitState::Exited(st) => Some(st),
+                _ => None
+            };
+        }
+
+        // Anything that explains the task's end is reported ahead of it
+        let mut reported = Vec::new();
+        let oom_killed = match res.state {
+            waitpid::WaitState::Signaled(ipc::signals::Signal::Kill) =>
+                self.cgroup.as_mut().map_or(false, |cg| cg.check_oom_kill()),
+            _ => false
+        };
+        if oom_killed {
+            reported.push(events::Event::new(res, events::State::OutOfMemory));
+        }
+
+        if !self.tasks.is_empty() {
+            reported.push(match res.state {
+                waitpid::WaitState::Signaled(sig) => events::Event::new(res, events::State::TaskKilled(sig)),
+                waitpid::WaitState::Exited(st) => events::Event::new(res, events::State::TaskExited(st)),
+                _ => unreachable!()
+            });
+            return self.queue(reported);
+        }
+
+        match self.cgroup_usage() {
+            Some(usage) => reported.push(events::Event::new(res, events::State::CgroupUsage(usage))),
+            None => {}
+        }
+        reported.push(match (res.state, self.exit_status) {
+            (_, Some(st)) => events::Event::new(res, events::State::Exit(st)),
+            (waitpid::WaitState::Signaled(sig), None) => events::Event::new(res, events::State::Killed(sig)),
+            (waitpid::WaitState::Exited(st), None) => events::Event::new(res, events::State::Exit(st)),
+            _ => unreachable!()
+        });
+        self.queue(reported)
+    }
+
+    /// Returns the first of several events, keeping the rest for the
+    /// following ticks
+    fn queue(&mut self, mut reported: Vec<events::Event>) -> events::Event {
+        let first = reported.remove(0);
+        self.pending.push_all(&reported[]);
+        self.deliver(first)
+    }
+
+    /// The sandbox stops running once the end of its last task is handed
+    /// out, rather than when it happens, so that anything queued ahead of it
+    /// is seen first
+    fn deliver(&mut self, event: events::Event) -> events::Event {
+        match event.state {
+            events::State::Exit(_) | events::State::Killed(_) => {
+                self.running = false;
+                self.pid = -1;
+            },
+            _ => {}
+        }
+        event
     }
 
-    fn handle_exec(&mut self, res: waitpid::WaitResult) -> events::Event {
+    /// Puts the new child in a cgroup of its own, if one is configured.
+    /// Failing that, the sandbox runs without one and the watcher is told why.
+    fn join_cgroup(&mut self) {
+        let config = match self.cgroup_config {
+            Some(ref config) => config.clone(),
+            None => return
+        };
+        let name = format!("sandbox-{}", self.pid);
+        let pid = self.pid;
+        match Cgroup::create(&config, &name[]).and_then(|cg| cg.add(pid).map(|_| cg)) {
+            Ok(cg) => self.cgroup = Some(cg),
+            Err(err) => {
+                let event = events::Event::for_pid(pid, events::State::CgroupUnavailable(errno::from_io_error(&err)));
+                self.pending.push(event);
+            }
+        }
+    }
+
+    fn handle_exec(&mut self, res: waitpid::WaitResult) -> SandboxResult<events::Event> {
         if !self.entered_main {
             self.entered_main = true;
-            return events::Event::new(res, events::State::EnteredMain);
+            Ok(events::Event::new(res, events::State::EnteredMain))
         } else {
-            self.release(ipc::signals::Signal::Kill);
-            return events::Event::new(res, events::State::Released(ipc::signals::Signal::Kill));
+            try!(self.release(ipc::signals::Signal::Kill));
+            Ok(events::Event::new(res, events::State::Released(ipc::signals::Signal::Kill)))
+        }
+    }
+
+    /// A call that fell through to an Errno default has its return value
+    /// set here, but the task is left stopped so that the Denied event can
+    /// be acted on first. The watcher has to resume it.
+    fn handle_seccomp(&mut self, res: waitpid::WaitResult) -> SandboxResult<events::Event> {
+        let call = ptrace::Syscall::from_pid(res.pid);
+        let msg = try!(get_event_msg(res.pid).map_err(SandboxError::Ptrace));
+        match (msg as u32, self.policy.get_default()) {
+            (policy::TRACE_DENIED, policy::Action::Errno(errno)) => {
+                let mut denied = call;
+                denied.call = -1;
+                denied.returnVal = -(errno as i64) as u64;
+                try!(denied.write().map_err(SandboxError::Ptrace));
+                Ok(events::Event::new(res, events::State::Denied(call)))
+            },
+            _ => Ok(events::Event::new(res, events::State::Seccomp(call)))
+        }
+    }
+
+    /// Waits for the next event and passes it to the watcher. Does nothing
+    /// once the sandbox has exited or been released.
+    pub fn tick(&mut self) -> SandboxResult<()> {
+        if self.pid <= 0 {
+            return Ok(());
+        }
+        let event = if self.deadline_ms.is_some() || self.idle_ms.is_some() {
+            try!(self.next_event_or_timeout())
+        } else {
+            try!(self.next_event(waitpid::All))
+        };
+        match event {
+            Some(event) => self.event_watch.notify_event(&event),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Like tick, but returns None instead of blocking when no task has
+    /// changed state. Any event is passed to the watcher before it is returned.
+    pub fn try_tick(&mut self) -> SandboxResult<Option<events::Event>> {
+        if self.pid <= 0 {
+            return Ok(None);
+        }
+        let event = match self.check_timeouts() {
+            Some(event) => Some(event),
+            None => try!(self.next_event(waitpid::All | waitpid::NoWait))
+        };
+        match event {
+            Some(ref e) => self.event_watch.notify_event(e),
+            None => {}
         }
+        Ok(event)
     }
 
-    pub fn tick(&mut self) {
-        let event = self.next_event();
-        self.event_watch.notify_event(&event);
+    /// How long until a timeout needs handling, for hosts that call try_tick
+    /// from an event loop. None if there is nothing to wait for.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.ms_until_timeout(now_ms()).map(|ms| Duration::milliseconds(ms as i64))
+    }
+
+    /// Kills the sandbox once it has run for this long
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline_ms = deadline.map(duration_ms);
+    }
+
+    /// Kills the sandbox once this long passes without any of its tasks
+    /// making a traced syscall or otherwise stopping
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_ms = timeout.map(duration_ms);
+    }
+
+    /// On a timeout, sends every task signal and waits this long before
+    /// killing them, instead of killing them straight away
+    pub fn set_grace_period(&mut self, grace: Option<(ipc::signals::Signal, Duration)>) {
+        self.grace = grace.map(|(sig, d)| (sig, duration_ms(d)));
+    }
+
+    /// Blocks like next_event, but only until the next timeout is due
+    fn next_event_or_timeout(&mut self) -> SandboxResult<Option<events::Event>> {
+        loop {
+            match self.check_timeouts() {
+                Some(event) => return Ok(Some(event)),
+                None => {}
+            }
+            match try!(self.next_event(waitpid::All | waitpid::NoWait)) {
+                Some(event) => return Ok(Some(event)),
+                None => {}
+            }
+            let due = self.ms_until_timeout(now_ms());
+            match self.child_events {
+                Some(ref fd) => fd.wait(match due {
+                    Some(ms) => cmp::min(ms, i32::MAX as u64) as libc::c_int,
+                    None => -1
+                }),
+                None => {
+                    let ms = cmp::min(due.unwrap_or(IDLE_POLL_MS), IDLE_POLL_MS);
+                    timer::sleep(Duration::milliseconds(ms as i64))
+                }
+            }
+        }
+    }
+
+    /// Acts on any timeout that has run out, returning the Timeout event
+    /// the first time one does
+    fn check_timeouts(&mut self) -> Option<events::Event> {
+        if self.pid <= 0 {
+            return None;
+        }
+        let now = now_ms();
+        match self.kill_at {
+            Some(at) if now >= at => {
+                self.kill_at = None;
+                self.signal_tasks(ipc::signals::Signal::Kill);
+                return None;
+            },
+            _ => {}
+        }
+        if self.timed_out {
+            return None;
+        }
+        let kind = match (self.deadline_ms, self.idle_ms) {
+            (Some(ms), _) if now >= self.started_at + ms => events::TimeoutKind::Deadline,
+            (_, Some(ms)) if now >= self.last_activity + ms => events::TimeoutKind::Idle,
+            _ => return None
+        };
+        self.timed_out = true;
+        match self.grace {
+            Some((sig, ms)) => {
+                self.signal_tasks(sig);
+                self.kill_at = Some(now + ms);
+            },
+            None => self.signal_tasks(ipc::signals::Signal::Kill)
+        }
+        Some(events::Event::for_pid(self.pid, events::State::Timeout(kind)))
+    }
+
+    fn ms_until_timeout(&self, now: u64) -> Option<u64> {
+        if self.pid <= 0 {
+            return None;
+        }
+        let mut due = self.kill_at;
+        if !self.timed_out {
+            let deadline = self.deadline_ms.map(|ms| self.started_at + ms);
+            let idle = self.idle_ms.map(|ms| self.last_activity + ms);
+            for at in [deadline, idle].iter() {
+                due = match (due, *at) {
+                    (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                    (a, None) => a,
+                    (None, b) => b
+                };
+            }
+        }
+        due.map(|at| if at > now { at - now } else { 0 })
+    }
+
+    /// Sends sig to the sandbox's process group, which takes in tasks we
+    /// haven't been told about yet, and to every known task in case it has
+    /// left the group. Tasks that have already died are skipped.
+    fn signal_tasks(&self, sig: ipc::signals::Signal) {
+        unsafe { kill(-self.pid, sig as libc::c_int); }
+        for task in self.tasks.iter() {
+            unsafe { kill(task.pid, sig as libc::c_int); }
+        }
+        for &pid in self.early_stops.iter() {
+            unsafe { kill(pid, sig as libc::c_int); }
+        }
+    }
+
+    /// Tasks that turn up after a timeout may have started too late for
+    /// signal_tasks to reach them, so they are killed as soon as they're seen
+    fn kill_if_timed_out(&self, pid: libc::pid_t) {
+        if self.timed_out {
+            unsafe { kill(pid, ipc::signals::Signal::Kill as libc::c_int); }
+        }
+    }
+
+    /// Whether spawn sets up an event_fd.
+    ///
+    /// The fd is a signalfd for SIGCHLD, so spawn blocks SIGCHLD for the
+    /// thread that calls it, and it stays blocked for good. Other threads
+    /// still get SIGCHLD as usual, and any of them may take one first and
+    /// leave the fd quiet, so multithreaded hosts should block SIGCHLD in
+    /// every thread or leave this off. Off by default, in which case tick
+    /// polls for timeouts instead.
+    pub fn set_event_fd(&mut self, enabled: bool) {
+        self.wants_event_fd = enabled;
+    }
+
+    /// A file descriptor for epoll and friends that becomes readable when the
+    /// sandbox may have events. Each time it does, call try_tick until it
+    /// returns None.
+    ///
+    /// None unless set_event_fd was turned on before spawning, or if the
+    /// kernel has no signalfd.
+    pub fn event_fd(&self) -> Option<libc::c_int> {
+        self.child_events.as_ref().map(|fd| fd.as_raw_fd())
     }
 
     pub fn is_running(&self) -> bool {
         self.running
     }
 
-    fn next_event(&mut self) -> events::Event {
-        assert!(self.pid > 0);
-        let s = waitpid::wait(-1, waitpid::All);
-        let res = s.ok().expect("Could not wait on child");
-        if res.pid == 0 && res.status == 0 {
-            return events::Event::new(res, events::State::None);
+    fn next_event(&mut self, opts: waitpid::Options) -> SandboxResult<Option<events::Event>> {
+        if !self.pending.is_empty() {
+            let event = self.pending.remove(0);
+            return Ok(Some(self.deliver(event)));
+        }
+        if self.pid <= 0 {
+            return Ok(None);
+        }
+        // Drained before waiting, so any SIGCHLD that arrives after the wait
+        // leaves the event fd readable
+        match self.child_events {
+            Some(ref fd) => fd.drain(),
+            None => {}
+        }
+        let res = try!(waitpid::wait(-1, opts));
+        if res.pid == 0 {
+            return Ok(None);
         }
-        match res.state {
+        self.last_activity = now_ms();
+        let event = match res.state {
             waitpid::WaitState::PTrace(e) =>
                 match e {
                     ptrace::Event::Exec => self.handle_exec(res),
-                    ptrace::Event::Seccomp =>
-                        events::Event::new(res, events::State::Seccomp(ptrace::Syscall::from_pid(res.pid))),
-                    ptrace::Event::Exit =>
-                        events::Event::new(res, events::State::Exit(0)),
-                    _ => panic!("Unhandled ptrace event {:?}", res)
+                    ptrace::Event::Seccomp => self.handle_seccomp(res),
+                    ptrace::Event::Clone | ptrace::Event::Fork | ptrace::Event::VFork =>
+                        self.handle_new_task(res, e),
+                    // Exit, and anything else we don't act on, is left to
+                    // the watcher
+                    _ => Ok(events::Event::new(res, events::State::PTrace(e)))
                 },
-            waitpid::WaitState::Stopped(s) => {
-                ptrace::cont(res.pid, s).ok().expect("Could not continue child");
-                return events::Event::new(res, events::State::Signal(s));
-            },
-            waitpid::WaitState::Exited(st) => {
-                self.release(ipc::signals::Signal::None);
-                return events::Event::new(res, events::State::Exit(st));
-            }
-            _ => panic!("Unknown state {:?}", res)
+            waitpid::WaitState::Stopped(s) => self.handle_stop(res, s),
+            waitpid::WaitState::Exited(_) | waitpid::WaitState::Signaled(_) =>
+                Ok(self.handle_task_end(res)),
+            waitpid::WaitState::Continued => Ok(events::Event::new(res, events::State::None))
+        };
+        match event {
+            // The task died while we were handling it, which is reported on
+            // its own once we wait again
+            Err(ref err) if err.is_vanished() => Ok(Some(events::Event::new(res, events::State::Vanished))),
+            other => other.map(Some)
         }
     }
 
+    pub fn get_limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Sets the resource limits for the next spawn
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Runs each spawn in a cgroup v2 group of its own beneath
+    /// config.parent, or not, if None
+    pub fn set_cgroup(&mut self, config: Option<CgroupConfig>) {
+        self.cgroup_config = config;
+    }
+
+    /// What the current or last spawn's cgroup has used, if it has one
+    pub fn cgroup_usage(&self) -> Option<CgroupUsage> {
+        self.cgroup.as_ref().and_then(|cg| cg.usage().ok())
+    }
+
     pub fn get_pid(&self) -> libc::pid_t {
         self.pid
     }
 
-    pub fn release(&mut self, signal: ipc::signals::Signal) {
-        ptrace::release(self.pid, signal);
+    /// The threads and processes currently traced by this sandbox
+    pub fn tasks(&self) -> &tasks::TaskTable {
+        &self.tasks
+    }
+
+    /// Stops tracing every task, sending each one signal. Tasks that have
+    /// already died are skipped, and the first other failure is returned
+    /// once every task has been tried.
+    pub fn release(&mut self, signal: ipc::signals::Signal) -> SandboxResult<()> {
+        let mut res = Ok(());
+        for task in self.tasks.iter() {
+            let r = ptrace::release(task.pid, signal).map(|_| ()).map_err(SandboxError::Ptrace);
+            if res.is_ok() {
+                res = ignore_vanished(r);
+            }
+        }
+        self.tasks.clear();
+        self.early_stops.clear();
+        self.pending.clear();
         self.pid = -1;
         self.running = false;
+        res
     }
 
-    pub fn spawn(&mut self) {
+    pub fn spawn(&mut self) -> SandboxResult<()> {
         self.running = true;
+        self.exit_status = None;
+        self.pending.clear();
+        self.started_at = now_ms();
+        self.last_activity = self.started_at;
+        self.timed_out = false;
+        self.kill_at = None;
+        // The last spawn's cgroup is empty by now, so this removes it
+        self.cgroup = None;
+        // Set up before forking so that no SIGCHLD can be missed
+        if self.wants_event_fd && self.child_events.is_none() {
+            self.child_events = signalfd::SignalFd::new(&[ipc::signals::Signal::Chld as libc::c_int]).ok();
+        }
+        let mut status = [-1 as libc::c_int; 2];
+        if unsafe { pipe2(status.as_mut_ptr(), O_CLOEXEC) } < 0 {
+            self.running = false;
+            return Err(SandboxError::Fork(os::errno()));
+        }
         self.pid = unsafe { fork() };
         match self.pid {
-            0 => self.exec_child(),
-            _ => self.attach_to_child()
+            -1 => {
+                let err = os::errno();
+                unsafe {
+                    close(status[0]);
+                    close(status[1]);
+                }
+                self.running = false;
+                Err(SandboxError::Fork(err))
+            },
+            0 => {
+                unsafe { close(status[0]); }
+                self.exec_child(status[1])
+            },
+            _ => {
+                unsafe { close(status[1]); }
+                let report = read_status(status[0]);
+                unsafe { close(status[0]); }
+                if report[0] != STATUS_READY {
+                    let _ = waitpid::wait(self.pid, waitpid::None);
+                    self.pid = -1;
+                    self.running = false;
+                    let errno = report[1] as usize;
+                    return Err(if report[0] == STATUS_LIMITS {
+                        SandboxError::Limits(errno)
+                    } else {
+                        SandboxError::Setup(errno)
+                    });
+                }
+                self.join_cgroup();
+                self.attach_to_child()
+            }
         }
     }
 }
 
+fn cont(pid: libc::pid_t, signal: ipc::signals::Signal) -> SandboxResult<()> {
+    ptrace::cont(pid, signal).map(|_| ()).map_err(SandboxError::Ptrace)
+}
+
+/// For ptrace requests on tasks other than the one an event is about: if
+/// such a task has died, that is reported by an event of its own.
+fn ignore_vanished(res: SandboxResult<()>) -> SandboxResult<()> {
+    match res {
+        Err(ref err) if err.is_vanished() => Ok(()),
+        other => other
+    }
+}
+
+fn get_event_msg(pid: libc::pid_t) -> Result<libc::c_ulong, usize> {
+    let mut msg: libc::c_ulong = 0;
+    let r = unsafe {
+        ptrace_raw(PTRACE_GETEVENTMSG, pid, 0 as *mut libc::c_void, &mut msg as *mut libc::c_ulong as *mut libc::c_void)
+    };
+    if r < 0 {
+        Err(::std::os::errno())
+    } else {
+        Ok(msg)
+    }
+}
+
+/// Reads the step and errno the child reports from exec_child, or EIO if
+/// it died before it could say
+fn read_status(fd: libc::c_int) -> [i32; 2] {
+    let mut report = [STATUS_READY, 0];
+    loop {
+        let r = unsafe { read(fd, report.as_mut_ptr() as *mut libc::c_void, 8) };
+        if r == 8 {
+            return report;
+        }
+        if r < 0 && os::errno() == libc::EINTR as usize {
+            continue;
+        }
+        return [STATUS_SETUP, libc::EIO];
+    }
+}
+
+fn get_siginfo(pid: libc::pid_t) -> Result<SigInfo, usize> {
+    let mut info = SigInfo {signo: 0, errno: 0, code: 0, _pad: 0, pid: 0, uid: 0, _rest: [0; 13]};
+    let r = unsafe {
+        ptrace_raw(PTRACE_GETSIGINFO, pid, 0 as *mut libc::c_void, &mut info as *mut SigInfo as *mut libc::c_void)
+    };
+    if r < 0 {
+        Err(::std::os::errno())
+    } else {
+        Ok(info)
+    }
+}
+
+/// Milliseconds on the monotonic clock
+fn now_ms() -> u64 {
+    let mut ts = Timespec {sec: 0, nsec: 0};
+    unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts); }
+    ts.sec as u64 * 1000 + ts.nsec as u64 / 1000000
+}
+
+fn duration_ms(d: Duration) -> u64 {
+    cmp::max(d.num_milliseconds(), 0) as u64
+}
+
+/// The start of a siginfo_t, as filled in for signals sent by kill()
+#[repr(C)]
+struct SigInfo {
+    signo: libc::c_int,
+    errno: libc::c_int,
+    code: libc::c_int,
+    _pad: libc::c_int,
+    pid: libc::pid_t,
+    uid: libc::uid_t,
+    _rest: [u64; 13]
+}
+
+#[repr(C)]
+struct Timespec {
+    sec: i64,
+    nsec: i64
+}
+
+const PTRACE_GETEVENTMSG: libc::c_int = 0x4201;
+const PTRACE_GETSIGINFO: libc::c_int = 0x4202;
+const O_CLOEXEC: libc::c_int = 0o2000000;
+const CLONE_THREAD: u64 = 0x00010000;
+const CLOCK_MONOTONIC: libc::c_int = 1;
+/// What the child reports through its status pipe before stopping
+const STATUS_READY: i32 = 0;
+const STATUS_LIMITS: i32 = 1;
+const STATUS_SETUP: i32 = 2;
+/// How often to check timeouts when there's no event fd to wait on
+pub const IDLE_POLL_MS: u64 = 10;
+
 extern "C" {
     fn fork() -> libc::pid_t;
+    fn pipe2(fds: *mut libc::c_int, flags: libc::c_int) -> libc::c_int;
+    fn read(fd: libc::c_int, buf: *mut libc::c_void, count: libc::size_t) -> libc::ssize_t;
+    fn write(fd: libc::c_int, buf: *const libc::c_void, count: libc::size_t) -> libc::ssize_t;
+    fn close(fd: libc::c_int) -> libc::c_int;
+    fn _exit(status: libc::c_int) -> !;
+    fn kill(pid: libc::pid_t, sig: libc::c_int) -> libc::c_int;
+    fn clock_gettime(clock: libc::c_int, ts: *mut Timespec) -> libc::c_int;
+    #[link_name = "ptrace"]
+    fn ptrace_raw(request: libc::c_int, pid: libc::pid_t, addr: *mut libc::c_void, data: *mut libc::c_void) -> libc::c_long;
 }
@@ -0,0 +1,114 @@
+#[allow(unstable)]
+extern crate libc;
+
+use std::os;
+use std::ptr;
+
+/// A non-blocking file descriptor that becomes readable while one of a set
+/// of signals is pending for the calling thread.
+///
+/// The signals are blocked for the calling thread so that they are only seen
+/// through the fd. They stay blocked after the SignalFd is dropped, as other
+/// SignalFds may still be relying on that.
+pub struct SignalFd {
+    fd: libc::c_int
+}
+
+impl SignalFd {
+    pub fn new(signals: &[libc::c_int]) -> Result<SignalFd, usize> {
+        let mask = SigSet::new(signals);
+        let r = unsafe { ext::pthread_sigmask(SIG_BLOCK, &mask, ptr::null_mut()) };
+        if r != 0 {
+            return Err(r as usize);
+        }
+        let fd = unsafe { ext::signalfd(-1, &mask, SFD_NONBLOCK | SFD_CLOEXEC) };
+        if fd < 0 {
+            Err(os::errno())
+        } else {
+            Ok(SignalFd {fd: fd})
+        }
+    }
+
+    pub fn as_raw_fd(&self) -> libc::c_int {
+        self.fd
+    }
+
+    /// Blocks until a signal is pending or timeout_ms have passed
+    pub fn wait(&self, timeout_ms: libc::c_int) {
+        let mut pfd = PollFd {fd: self.fd, events: POLLIN, revents: 0};
+        unsafe { ext::poll(&mut pfd, 1, timeout_ms); }
+    }
+
+    /// Reads every pending signal, so that the fd stays quiet until another
+    /// one arrives
+    pub fn drain(&self) {
+        let mut info = [0u8; SIGINFO_SIZE];
+        loop {
+            let r = unsafe {
+                ext::read(self.fd, info.as_mut_ptr() as *mut libc::c_void, SIGINFO_SIZE as libc::size_t)
+            };
+            if r <= 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        unsafe { ext::close(self.fd); }
+    }
+}
+
+/// Unblocks signals for the calling thread, undoing SignalFd::new. Used by
+/// forked children, which would otherwise inherit the blocked mask across
+/// exec.
+pub fn unblock(signals: &[libc::c_int]) -> Result<(), usize> {
+    let mask = SigSet::new(signals);
+    match unsafe { ext::pthread_sigmask(SIG_UNBLOCK, &mask, ptr::null_mut()) } {
+        0 => Ok(()),
+        r => Err(r as usize)
+    }
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: libc::c_int,
+    events: libc::c_short,
+    revents: libc::c_short
+}
+
+#[repr(C)]
+struct SigSet {
+    bits: [u64; 16]
+}
+
+impl SigSet {
+    fn new(signals: &[libc::c_int]) -> SigSet {
+        let mut set = SigSet {bits: [0; 16]};
+        for &sig in signals.iter() {
+            let bit = (sig - 1) as usize;
+            set.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        set
+    }
+}
+
+const SIG_BLOCK: libc::c_int = 0;
+const SIG_UNBLOCK: libc::c_int = 1;
+const SFD_NONBLOCK: libc::c_int = 0o4000;
+const SFD_CLOEXEC: libc::c_int = 0o2000000;
+const SIGINFO_SIZE: usize = 128;
+const POLLIN: libc::c_short = 1;
+
+mod ext {
+    use super::libc;
+    use super::{SigSet, PollFd};
+    extern "C" {
+        pub fn pthread_sigmask(how: libc::c_int, set: *const SigSet, old: *mut SigSet) -> libc::c_int;
+        pub fn signalfd(fd: libc::c_int, mask: *const SigSet, flags: libc::c_int) -> libc::c_int;
+        pub fn read(fd: libc::c_int, buf: *mut libc::c_void, count: libc::size_t) -> libc::ssize_t;
+        pub fn close(fd: libc::c_int) -> libc::c_int;
+        pub fn poll(fds: *mut PollFd, nfds: libc::c_ulong, timeout: libc::c_int) -> libc::c_int;
+    }
+}
@@ -0,0 +1,91 @@
+#[allow(unstable)]
+extern crate libc;
+extern crate futures;
+
+use self::futures::{Async, Poll};
+use self::futures::stream::Stream;
+use self::futures::task;
+use events;
+use error::SandboxError;
+use sandbox::{Sandbox, IDLE_POLL_MS};
+use std::thread;
+use std::cmp;
+use std::i32;
+use std::io::timer;
+use std::time::Duration;
+
+/// A futures Stream of a sandbox's events, which ends once the sandbox stops
+/// running. The sandbox's watcher still sees every event first.
+///
+/// Turn on Sandbox::set_event_fd before spawning so that the stream wakes
+/// when a task changes state. Without it, the stream checks every
+/// IDLE_POLL_MS instead.
+pub struct Events<'s, 'a: 's, 'b: 's> {
+    sandbox: &'s mut Sandbox<'a, 'b>
+}
+
+impl<'s, 'a, 'b> Events<'s, 'a, 'b> {
+    pub fn new(sandbox: &'s mut Sandbox<'a, 'b>) -> Self {
+        Events {
+            sandbox: sandbox
+        }
+    }
+}
+
+impl<'s, 'a, 'b> Stream for Events<'s, 'a, 'b> {
+    type Item = events::Event;
+    type Error = SandboxError;
+
+    fn poll(&mut self) -> Poll<Option<events::Event>, SandboxError> {
+        if !self.sandbox.is_running() {
+            return Ok(Async::Ready(None));
+        }
+        match try!(self.sandbox.try_tick()) {
+            Some(event) => Ok(Async::Ready(Some(event))),
+            None => {
+                // Nothing owns the event fd's readiness, so park a thread on
+                // it to wake the task
+                let waiter = task::current();
+                let timeout = match self.sandbox.next_timeout() {
+                    Some(d) => cmp::min(d.num_milliseconds(), i32::MAX as i64) as libc::c_int,
+                    None => -1
+                };
+                let fd = self.sandbox.event_fd();
+                thread::spawn(move || {
+                    match fd {
+                        Some(fd) => wait_readable(fd, timeout),
+                        None => {
+                            let ms = if timeout < 0 { IDLE_POLL_MS as libc::c_int } else { cmp::min(timeout, IDLE_POLL_MS as libc::c_int) };
+                            timer::sleep(Duration::milliseconds(ms as i64))
+                        }
+                    }
+                    waiter.notify();
+                });
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+/// Waits for fd to become readable, or for the sandbox's next timeout
+fn wait_readable(fd: libc::c_int, timeout_ms: libc::c_int) {
+    let mut pfd = PollFd {fd: fd, events: POLLIN, revents: 0};
+    unsafe { ext::poll(&mut pfd, 1, timeout_ms); }
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: libc::c_int,
+    events: libc::c_short,
+    revents: libc::c_short
+}
+
+const POLLIN: libc::c_short = 1;
+
+mod ext {
+    use super::libc;
+    use super::PollFd;
+    extern "C" {
+        pub fn poll(fds: *mut PollFd, nfds: libc::c_ulong, timeout: libc::c_int) -> libc::c_int;
+    }
+}
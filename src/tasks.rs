@@ -0,0 +1,117 @@
+#[allow(unstable)]
+extern crate libc;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Values;
+
+#[derive(Show, Copy, Clone, PartialEq)]
+pub enum Kind {
+    Process,
+    Thread
+}
+
+/// A single traced thread or process inside the sandbox
+#[derive(Show, Copy, Clone)]
+pub struct Task {
+    pub pid: libc::pid_t,
+    pub parent: libc::pid_t,
+    pub kind: Kind,
+    started: bool
+}
+
+impl Task {
+    /// Whether the task has been seen running after its initial SIGSTOP
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+}
+
+/// Every task currently traced by a sandbox, along with who spawned it.
+///
+/// The root task has a parent of -1.
+pub struct TaskTable {
+    tasks: HashMap<libc::pid_t, Task>
+}
+
+impl TaskTable {
+    pub fn new() -> TaskTable {
+        TaskTable {
+            tasks: HashMap::new()
+        }
+    }
+
+    pub fn insert_root(&mut self, pid: libc::pid_t) {
+        self.tasks.insert(pid, Task {
+            pid: pid,
+            parent: -1,
+            kind: Kind::Process,
+            started: true
+        });
+    }
+
+    pub fn insert(&mut self, pid: libc::pid_t, parent: libc::pid_t, kind: Kind) {
+        self.tasks.insert(pid, Task {
+            pid: pid,
+            parent: parent,
+            kind: kind,
+            started: false
+        });
+    }
+
+    /// Marks a task as started, returning true if it was waiting on its
+    /// initial stop.
+    pub fn start(&mut self, pid: libc::pid_t) -> bool {
+        match self.tasks.get_mut(&pid) {
+            Some(t) => {
+                let was_pending = !t.started;
+                t.started = true;
+                was_pending
+            },
+            None => false
+        }
+    }
+
+    pub fn remove(&mut self, pid: libc::pid_t) -> Option<Task> {
+        self.tasks.remove(&pid)
+    }
+
+    pub fn get(&self, pid: libc::pid_t) -> Option<&Task> {
+        self.tasks.get(&pid)
+    }
+
+    /// The process that pid is a thread of, or pid itself if it's a process
+    /// or its process is no longer traced
+    pub fn process_of(&self, pid: libc::pid_t) -> libc::pid_t {
+        let mut pid = pid;
+        loop {
+            match self.tasks.get(&pid) {
+                Some(t) if t.kind == Kind::Thread && self.tasks.contains_key(&t.parent) => pid = t.parent,
+                _ => return pid
+            }
+        }
+    }
+
+    pub fn contains(&self, pid: libc::pid_t) -> bool {
+        self.tasks.contains_key(&pid)
+    }
+
+    pub fn children(&self, pid: libc::pid_t) -> Vec<libc::pid_t> {
+        self.tasks.values().filter(|t| t.parent == pid).map(|t| t.pid).collect()
+    }
+
+    pub fn iter(&self) -> Values<libc::pid_t, Task> {
+        self.tasks.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+    }
+}
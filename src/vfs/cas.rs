@@ -0,0 +1,286 @@
+#[allow(unstable)]
+extern crate libc;
+extern crate crypto;
+
+use vfs;
+use vfs::errno;
+use vfs::path;
+use io;
+use self::crypto::digest::Digest;
+use self::crypto::sha2::Sha256;
+use std::collections::{HashMap, BTreeSet};
+use std::io::{IoResult, IoError, FileStat, FileType, SeekStyle, File};
+use std::io::fs;
+use std::ascii::AsciiExt;
+use std::cmp;
+
+enum Kind {
+    Dir(BTreeSet<String>),
+    Blob(String)
+}
+
+struct Entry {
+    kind: Kind,
+    inode: u64
+}
+
+struct OpenBlob {
+    path: String,
+    data: Vec<u8>,
+    pos: usize
+}
+
+/// Serves files out of a content-addressed store, read-only.
+///
+/// The manifest maps each path to the SHA-256 of its contents, and the
+/// contents live in the store directory under their lowercase hex hash.
+/// Every blob is hashed again when it is opened and the verified bytes are
+/// what the sandbox reads, so a store modified behind our back fails with
+/// EIO instead of running something that wasn't deployed.
+pub struct CasFS {
+    store: Path,
+    entries: HashMap<String, Entry>,
+    next_ino: u64,
+    open_blobs: HashMap<i32, OpenBlob>,
+    next_fd: i32
+}
+
+impl CasFS {
+    pub fn new(store: Path, manifest: &[(String, String)]) -> IoResult<CasFS> {
+        let mut cas = CasFS {
+            store: store,
+            entries: HashMap::new(),
+            next_ino: 1,
+            open_blobs: HashMap::new(),
+            next_fd: 0
+        };
+        cas.insert(String::from_str("/"), Kind::Dir(BTreeSet::new()));
+        for &(ref file, ref hash) in manifest.iter() {
+            if !is_sha256(&hash[]) {
+                return Err(errno::error(libc::EINVAL));
+            }
+            cas.insert(path::normalize("/", &file[]), Kind::Blob(hash.to_ascii_lowercase()));
+        }
+        Ok(cas)
+    }
+
+    /// Reads a manifest in the format sha256sum prints, one "<hash>  <path>"
+    /// per line.
+    pub fn from_manifest(store: Path, manifest: &Path) -> IoResult<CasFS> {
+        let text = try!(File::open(manifest).and_then(|mut f| f.read_to_string()));
+        let mut entries = Vec::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let mut fields = line.splitn(1, ' ');
+            match (fields.next(), fields.next()) {
+                (Some(hash), Some(file)) => {
+                    // sha256sum marks binary mode with a '*' before the name
+                    let file = file.trim_left_matches(' ').trim_left_matches('*');
+                    entries.push((String::from_str(file), String::from_str(hash)));
+                },
+                _ => return Err(errno::error(libc::EINVAL))
+            }
+        }
+        CasFS::new(store, &entries[])
+    }
+
+    fn insert(&mut self, path: String, kind: Kind) {
+        if path != "/" {
+            let parent = path::parent(&path[]);
+            if !self.entries.contains_key(&parent) {
+                self.insert(parent.clone(), Kind::Dir(BTreeSet::new()));
+            }
+            let name = String::from_str(*path::components(&path[]).last().unwrap());
+            match self.entries.get_mut(&parent) {
+                Some(&mut Entry {kind: Kind::Dir(ref mut names), ..}) => {
+                    names.insert(name);
+                },
+                _ => {}
+            }
+        }
+        let inode = match self.entries.get(&path) {
+            Some(&Entry {kind: Kind::Dir(_), ..}) => return,
+            Some(entry) => entry.inode,
+            None => {
+                let inode = self.next_ino;
+                self.next_ino += 1;
+                inode
+            }
+        };
+        self.entries.insert(path, Entry {kind: kind, inode: inode});
+    }
+
+    fn lookup(&self, path: &str) -> IoResult<&Entry> {
+        match self.entries.get(path) {
+            Some(entry) => Ok(entry),
+            None => Err(errno::error(libc::ENOENT))
+        }
+    }
+
+    fn blob_path(&self, hash: &str) -> Path {
+        self.store.join(hash)
+    }
+
+    /// Reads the blob for hash, failing if its contents don't match
+    fn read_blob(&self, hash: &str) -> IoResult<Vec<u8>> {
+        let data = try!(File::open(&self.blob_path(hash)).and_then(|mut f| f.read_to_end()));
+        let mut hasher = Sha256::new();
+        hasher.input(&data[]);
+        if hasher.result_str() != hash {
+            return Err(corrupt_blob());
+        }
+        Ok(data)
+    }
+
+    fn stat_entry(&self, entry: &Entry) -> IoResult<FileStat> {
+        match entry.kind {
+            Kind::Dir(_) => Ok(vfs::synthetic_stat(FileType::Directory, 0o555, 0, entry.inode)),
+            Kind::Blob(ref hash) => {
+                let host = try!(fs::stat(&self.blob_path(&hash[])));
+                let mut st = vfs::synthetic_stat(FileType::RegularFile, 0o444, host.size, entry.inode);
+                st.created = host.created;
+                st.modified = host.modified;
+                st.accessed = host.accessed;
+                Ok(st)
+            }
+        }
+    }
+
+    fn get_open(&mut self, handle: &io::Handle) -> IoResult<&mut OpenBlob> {
+        match self.open_blobs.get_mut(&handle.get_local_fd()) {
+            Some(b) => Ok(b),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_digit(16))
+}
+
+fn corrupt_blob() -> IoError {
+    IoError {
+        detail: Some(String::from_str("blob does not match its hash")),
+        ..errno::error(libc::EIO)
+    }
+}
+
+fn read_only() -> IoError {
+    errno::error(libc::EROFS)
+}
+
+impl vfs::Filesystem for CasFS {
+    fn do_open(&mut self, path: &str, flags: vfs::OpenFlags, _mode: u32) -> IoResult<i32> {
+        if flags.is_writable() || flags.contains(vfs::Create) {
+            return Err(read_only());
+        }
+        let data = match try!(self.lookup(path)).kind {
+            Kind::Blob(_) if flags.contains(vfs::Directory) => return Err(errno::error(libc::ENOTDIR)),
+            Kind::Blob(ref hash) => try!(self.read_blob(&hash[])),
+            Kind::Dir(_) => Vec::new()
+        };
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.open_blobs.insert(fd, OpenBlob {
+            path: String::from_str(path),
+            data: data,
+            pos: 0
+        });
+        Ok(fd)
+    }
+
+    fn do_access(&self, path: &str) -> IoResult<()> {
+        self.lookup(path).map(|_| ())
+    }
+
+    fn do_stat(&self, path: &str) -> IoResult<FileStat> {
+        self.do_lstat(path)
+    }
+
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat> {
+        let entry = try!(self.lookup(path));
+        self.stat_entry(entry)
+    }
+
+    fn do_readlink(&self, path: &str) -> IoResult<String> {
+        try!(self.lookup(path));
+        Err(errno::error(libc::EINVAL))
+    }
+
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat> {
+        let (path, size) = {
+            let blob = try!(self.get_open(handle));
+            (blob.path.clone(), blob.data.len() as u64)
+        };
+        let mut st = try!(self.do_lstat(&path[]));
+        if st.kind == FileType::RegularFile {
+            // Report what was verified, not whatever is in the store now
+            st.size = size;
+        }
+        Ok(st)
+    }
+
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<vfs::DirEntry>> {
+        let path = try!(self.get_open(handle)).path.clone();
+        match try!(self.lookup(&path[])).kind {
+            Kind::Dir(ref names) => {
+                let mut entries = Vec::new();
+                for name in names.iter() {
+                    let child = try!(self.lookup(&path::normalize(&path[], &name[])[]));
+                    entries.push(vfs::DirEntry {
+                        name: name.clone(),
+                        inode: child.inode,
+                        kind: match child.kind {
+                            Kind::Dir(_) => FileType::Directory,
+                            Kind::Blob(_) => FileType::RegularFile
+                        }
+                    });
+                }
+                Ok(entries)
+            },
+            _ => Err(errno::error(libc::ENOTDIR))
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+impl io::Streaming for CasFS {
+    fn do_write(&mut self, _handle: &io::Handle, _buf: &[u8]) -> IoResult<usize> {
+        Err(read_only())
+    }
+
+    fn do_read(&mut self, handle: &io::Handle, buf: &mut [u8]) -> IoResult<usize> {
+        let blob = try!(self.get_open(handle));
+        let start = cmp::min(blob.pos, blob.data.len());
+        let len = cmp::min(buf.len(), blob.data.len() - start);
+        for i in range(0, len) {
+            buf[i] = blob.data[start + i];
+        }
+        blob.pos = start + len;
+        Ok(len)
+    }
+
+    fn do_seek(&mut self, handle: &io::Handle, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        let blob = try!(self.get_open(handle));
+        let base = match whence {
+            SeekStyle::SeekSet => 0,
+            SeekStyle::SeekCur => blob.pos as i64,
+            SeekStyle::SeekEnd => blob.data.len() as i64
+        };
+        blob.pos = match base.checked_add(offset) {
+            Some(p) if p < 0 => return Err(errno::error(libc::EINVAL)),
+            Some(p) => p as usize,
+            None => return Err(errno::error(libc::EOVERFLOW))
+        };
+        Ok(blob.pos as u64)
+    }
+
+    fn do_close(&mut self, handle: &io::Handle) -> IoResult<()> {
+        match self.open_blobs.remove(&handle.get_local_fd()) {
+            Some(_) => Ok(()),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
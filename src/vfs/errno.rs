@@ -0,0 +1,101 @@
+#[allow(unstable)]
+extern crate libc;
+
+use std::io::{IoError, IoErrorKind};
+use std::ascii::AsciiExt;
+
+/// The errors a filesystem is expected to return, with the IoErrorKind and
+/// description each one is constructed with.
+static ERRORS: &'static [(libc::c_int, IoErrorKind, &'static str)] = &[
+    (libc::ENOENT, IoErrorKind::FileNotFound, "No such file or directory"),
+    (libc::EACCES, IoErrorKind::PermissionDenied, "Permission denied"),
+    (libc::EPERM, IoErrorKind::PermissionDenied, "Operation not permitted"),
+    (libc::EROFS, IoErrorKind::PermissionDenied, "Read-only file system"),
+    (libc::EEXIST, IoErrorKind::PathAlreadyExists, "File exists"),
+    (libc::ENOTDIR, IoErrorKind::MismatchedFileTypeForOperation, "Not a directory"),
+    (libc::EISDIR, IoErrorKind::MismatchedFileTypeForOperation, "Is a directory"),
+    (libc::EBADF, IoErrorKind::InvalidInput, "Bad file descriptor"),
+    (libc::EINVAL, IoErrorKind::InvalidInput, "Invalid argument"),
+    (libc::ESPIPE, IoErrorKind::InvalidInput, "Illegal seek"),
+    (libc::ENAMETOOLONG, IoErrorKind::InvalidInput, "File name too long"),
+    (libc::ENOSPC, IoErrorKind::OtherIoError, "No space left on device"),
+    (libc::EDQUOT, IoErrorKind::OtherIoError, "Disk quota exceeded"),
+    (libc::EFBIG, IoErrorKind::OtherIoError, "File too large"),
+    (libc::EOVERFLOW, IoErrorKind::InvalidInput, "Value too large for defined data type"),
+    (libc::ENFILE, IoErrorKind::ResourceUnavailable, "Too many open files in system"),
+    (libc::EMFILE, IoErrorKind::ResourceUnavailable, "Too many open files"),
+    (libc::EAGAIN, IoErrorKind::ResourceUnavailable, "Resource temporarily unavailable"),
+    (libc::ELOOP, IoErrorKind::OtherIoError, "Too many levels of symbolic links"),
+    (libc::ENOTEMPTY, IoErrorKind::OtherIoError, "Directory not empty"),
+    (libc::EBUSY, IoErrorKind::ResourceUnavailable, "Device or resource busy"),
+    (libc::EXDEV, IoErrorKind::OtherIoError, "Invalid cross-device link"),
+    (libc::ENOSYS, IoErrorKind::IoUnavailable, "Function not implemented"),
+    (libc::EPIPE, IoErrorKind::BrokenPipe, "Broken pipe"),
+    (libc::EIO, IoErrorKind::OtherIoError, "Input/output error")
+];
+
+/// Descriptions std gives some errnos in place of their strerror() text
+static STD_DESCRIPTIONS: &'static [(libc::c_int, &'static str)] = &[
+    (libc::EISDIR, "illegal operation on a directory")
+];
+
+/// Builds the IoError a filesystem should return for errno.
+///
+/// to_errno() maps errors built this way back to the same errno, which
+/// IoErrorKind alone can't do since several errnos share a kind.
+pub fn error(errno: libc::c_int) -> IoError {
+    for &(e, kind, desc) in ERRORS.iter() {
+        if e == errno {
+            return IoError {kind: kind, desc: desc, detail: None};
+        }
+    }
+    IoError {kind: IoErrorKind::OtherIoError, desc: "Unknown error", detail: None}
+}
+
+/// Works out the positive errno that err stands for.
+///
+/// Errors from error() are matched by description. Errors from the host
+/// start their detail with the strerror() text, or their description if std
+/// rewrote it, followed by the path and such. Anything else falls back to a
+/// best guess from its kind.
+pub fn from_io_error(err: &IoError) -> libc::c_int {
+    let known = ERRORS.iter().map(|&(e, _, desc)| (e, desc)).chain(STD_DESCRIPTIONS.iter().map(|&x| x));
+    for (e, desc) in known {
+        if describes(err.desc, desc) {
+            return e;
+        }
+        match err.detail {
+            Some(ref detail) if describes(&detail[], desc) => return e,
+            _ => {}
+        }
+    }
+
+    match err.kind {
+        IoErrorKind::FileNotFound | IoErrorKind::PathDoesntExist => libc::ENOENT,
+        IoErrorKind::PermissionDenied => libc::EACCES,
+        IoErrorKind::PathAlreadyExists => libc::EEXIST,
+        IoErrorKind::MismatchedFileTypeForOperation => libc::ENOTDIR,
+        IoErrorKind::InvalidInput => libc::EINVAL,
+        IoErrorKind::ResourceUnavailable => libc::EAGAIN,
+        IoErrorKind::BrokenPipe => libc::EPIPE,
+        IoErrorKind::Closed => libc::EBADF,
+        IoErrorKind::TimedOut => libc::ETIMEDOUT,
+        IoErrorKind::ConnectionRefused => libc::ECONNREFUSED,
+        IoErrorKind::ConnectionReset => libc::ECONNRESET,
+        IoErrorKind::ConnectionAborted => libc::ECONNABORTED,
+        IoErrorKind::NotConnected => libc::ENOTCONN,
+        IoErrorKind::IoUnavailable => libc::ENOSYS,
+        _ => libc::EIO
+    }
+}
+
+/// Whether text is desc, ignoring case, or desc followed by the details
+/// std appends to host errors
+fn describes(text: &str, desc: &str) -> bool {
+    let (text, desc) = (text.as_bytes(), desc.as_bytes());
+    if text.len() < desc.len() || !text[..desc.len()].eq_ignore_ascii_case(desc) {
+        return false;
+    }
+    let rest = &text[desc.len()..];
+    rest.is_empty() || rest.starts_with(b";") || rest.starts_with(b" (")
+}
@@ -0,0 +1,577 @@
+#[allow(unstable)]
+extern crate libc;
+
+use vfs;
+use vfs::errno;
+use vfs::path;
+use io;
+use std::collections::{HashMap, BTreeMap};
+use std::io::{IoResult, FileStat, FileType, SeekStyle};
+use std::iter::repeat;
+use std::cmp;
+use std::ptr;
+
+enum Content {
+    Dir(BTreeMap<String, u64>),
+    File(Vec<u8>),
+    Link(String)
+}
+
+struct Inode {
+    content: Content,
+    perm: u32,
+    nlink: u64,
+    created: u64,
+    modified: u64,
+    accessed: u64
+}
+
+impl Inode {
+    fn new(content: Content, perm: u32) -> Inode {
+        let now = now_ms();
+        Inode {
+            content: content,
+            perm: perm,
+            nlink: 0,
+            created: now,
+            modified: now,
+            accessed: now
+        }
+    }
+
+    fn kind(&self) -> FileType {
+        match self.content {
+            Content::Dir(_) => FileType::Directory,
+            Content::File(_) => FileType::RegularFile,
+            Content::Link(_) => FileType::Symlink
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self.content {
+            Content::Dir(_) => 0,
+            Content::File(ref data) => data.len() as u64,
+            Content::Link(ref target) => target.len() as u64
+        }
+    }
+}
+
+struct OpenFile {
+    ino: u64,
+    pos: u64,
+    flags: vfs::OpenFlags
+}
+
+const ROOT_INO: u64 = 1;
+
+/// The largest a file can grow unless set_max_file_size says otherwise
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 256 << 20;
+
+/// A filesystem that lives entirely in memory, for scratch space and for
+/// tests that shouldn't touch the host's disk.
+///
+/// With a quota, writes that would grow the total size of all files past it
+/// fail with ENOSPC. Growing any one file past its maximum size fails with
+/// EFBIG.
+pub struct MemFS {
+    inodes: HashMap<u64, Inode>,
+    next_ino: u64,
+    open_files: HashMap<i32, OpenFile>,
+    next_fd: i32,
+    max_bytes: Option<u64>,
+    used_bytes: u64,
+    max_file_size: u64
+}
+
+impl MemFS {
+    pub fn new() -> MemFS {
+        let mut inodes = HashMap::new();
+        let mut root = Inode::new(Content::Dir(BTreeMap::new()), 0o755);
+        root.nlink = 1;
+        inodes.insert(ROOT_INO, root);
+        MemFS {
+            inodes: inodes,
+            next_ino: ROOT_INO + 1,
+            open_files: HashMap::new(),
+            next_fd: 0,
+            max_bytes: None,
+            used_bytes: 0,
+            max_file_size: DEFAULT_MAX_FILE_SIZE
+        }
+    }
+
+    pub fn with_quota(max_bytes: u64) -> MemFS {
+        let mut fs = MemFS::new();
+        fs.max_bytes = Some(max_bytes);
+        fs
+    }
+
+    /// Total size of every file's contents
+    pub fn used_bytes(&self) -> u64 {
+        self.used_bytes
+    }
+
+    pub fn set_max_file_size(&mut self, bytes: u64) {
+        self.max_file_size = bytes;
+    }
+
+    pub fn create_dir(&mut self, path: &str, perm: u32) -> IoResult<()> {
+        self.create(path, Content::Dir(BTreeMap::new()), perm).map(|_| ())
+    }
+
+    pub fn create_file(&mut self, path: &str, contents: &[u8], perm: u32) -> IoResult<()> {
+        if contents.len() as u64 > self.max_file_size {
+            return Err(errno::error(libc::EFBIG));
+        }
+        try!(self.reserve(contents.len() as u64));
+        match self.create(path, Content::File(contents.to_vec()), perm) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.used_bytes -= contents.len() as u64;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn create_symlink(&mut self, path: &str, target: &str) -> IoResult<()> {
+        self.create(path, Content::Link(String::from_str(target)), 0o777).map(|_| ())
+    }
+
+    fn reserve(&mut self, bytes: u64) -> IoResult<()> {
+        match self.max_bytes {
+            Some(max) if self.used_bytes + bytes > max => Err(errno::error(libc::ENOSPC)),
+            _ => {
+                self.used_bytes += bytes;
+                Ok(())
+            }
+        }
+    }
+
+    /// Finds the inode at path without following symlinks. The VFS has
+    /// already resolved any links along the way.
+    fn lookup(&self, path: &str) -> IoResult<u64> {
+        let mut ino = ROOT_INO;
+        for name in path::components(path).iter() {
+            ino = match self.inodes[ino].content {
+                Content::Dir(ref entries) => match entries.get(*name) {
+                    Some(child) => *child,
+                    None => return Err(errno::error(libc::ENOENT))
+                },
+                _ => return Err(errno::error(libc::ENOTDIR))
+            };
+        }
+        Ok(ino)
+    }
+
+    fn lookup_parent(&self, path: &str) -> IoResult<(u64, String)> {
+        let parts = path::components(path);
+        let name = match parts.last() {
+            Some(name) => String::from_str(*name),
+            None => return Err(errno::error(libc::EEXIST))
+        };
+        let parent = try!(self.lookup(&path::parent(path)[]));
+        match self.inodes[parent].content {
+            Content::Dir(_) => Ok((parent, name)),
+            _ => Err(errno::error(libc::ENOTDIR))
+        }
+    }
+
+    fn create(&mut self, path: &str, content: Content, perm: u32) -> IoResult<u64> {
+        let ino = self.next_ino;
+        self.inodes.insert(ino, Inode::new(content, perm & 0o7777));
+        match self.link_entry(path, ino) {
+            Ok(_) => {
+                self.next_ino += 1;
+                Ok(ino)
+            },
+            Err(e) => {
+                self.inodes.remove(&ino);
+                Err(e)
+            }
+        }
+    }
+
+    /// Adds a directory entry for ino at path
+    fn link_entry(&mut self, path: &str, ino: u64) -> IoResult<()> {
+        let (parent, name) = try!(self.lookup_parent(path));
+        match self.inodes.get_mut(&parent).unwrap().content {
+            Content::Dir(ref mut entries) => {
+                if entries.contains_key(&name) {
+                    return Err(errno::error(libc::EEXIST));
+                }
+                entries.insert(name, ino);
+            },
+            _ => return Err(errno::error(libc::ENOTDIR))
+        }
+        self.inodes.get_mut(&ino).unwrap().nlink += 1;
+        Ok(())
+    }
+
+    /// Removes the directory entry at path, returning the inode it named.
+    /// The inode itself stays until release_inode finds it unused.
+    fn unlink_entry(&mut self, path: &str) -> IoResult<u64> {
+        let (parent, name) = try!(self.lookup_parent(path));
+        let ino = match self.inodes.get_mut(&parent).unwrap().content {
+            Content::Dir(ref mut entries) => match entries.remove(&name) {
+                Some(ino) => ino,
+                None => return Err(errno::error(libc::ENOENT))
+            },
+            _ => return Err(errno::error(libc::ENOTDIR))
+        };
+        self.inodes.get_mut(&ino).unwrap().nlink -= 1;
+        Ok(ino)
+    }
+
+    /// Frees an inode once no entry links to it and no handle has it open
+    fn release_inode(&mut self, ino: u64) {
+        if self.inodes[ino].nlink > 0 || self.open_files.values().any(|f| f.ino == ino) {
+            return;
+        }
+        match self.inodes.remove(&ino).unwrap().content {
+            Content::File(ref data) => self.used_bytes -= data.len() as u64,
+            _ => {}
+        }
+    }
+
+    fn is_dir(&self, ino: u64) -> bool {
+        match self.inodes[ino].content {
+            Content::Dir(_) => true,
+            _ => false
+        }
+    }
+
+    fn is_empty_dir(&self, ino: u64) -> bool {
+        match self.inodes[ino].content {
+            Content::Dir(ref entries) => entries.is_empty(),
+            _ => false
+        }
+    }
+
+    fn truncate_inode(&mut self, ino: u64, len: u64) -> IoResult<()> {
+        let size = match self.inodes[ino].content {
+            Content::File(ref data) => data.len() as u64,
+            Content::Dir(_) => return Err(errno::error(libc::EISDIR)),
+            Content::Link(_) => return Err(errno::error(libc::EINVAL))
+        };
+        if len > self.max_file_size {
+            return Err(errno::error(libc::EFBIG));
+        }
+        if len > size {
+            try!(self.reserve(len - size));
+        } else {
+            self.used_bytes -= size - len;
+        }
+        let inode = self.inodes.get_mut(&ino).unwrap();
+        match inode.content {
+            Content::File(ref mut data) => {
+                if len as usize > data.len() {
+                    let grow = len as usize - data.len();
+                    data.extend(repeat(0u8).take(grow));
+                } else {
+                    data.truncate(len as usize);
+                }
+            },
+            _ => {}
+        }
+        inode.modified = now_ms();
+        Ok(())
+    }
+
+    fn stat_inode(&self, ino: u64) -> FileStat {
+        let inode = &self.inodes[ino];
+        let mut st = vfs::synthetic_stat(inode.kind(), inode.perm, inode.size(), ino);
+        st.unstable.nlink = inode.nlink;
+        st.created = inode.created;
+        st.modified = inode.modified;
+        st.accessed = inode.accessed;
+        st
+    }
+
+    fn get_open(&mut self, handle: &io::Handle) -> IoResult<&mut OpenFile> {
+        match self.open_files.get_mut(&handle.get_local_fd()) {
+            Some(f) => Ok(f),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    unsafe { libc::time(ptr::null_mut()) as u64 * 1000 }
+}
+
+impl vfs::Filesystem for MemFS {
+    fn do_open(&mut self, path: &str, flags: vfs::OpenFlags, mode: u32) -> IoResult<i32> {
+        let ino = match self.lookup(path) {
+            Ok(ino) => {
+                if flags.contains(vfs::Create | vfs::Exclusive) {
+                    return Err(errno::error(libc::EEXIST));
+                }
+                ino
+            },
+            Err(ref e) if errno::from_io_error(e) == libc::ENOENT && flags.contains(vfs::Create) => {
+                if flags.contains(vfs::Directory) {
+                    return Err(errno::error(libc::EINVAL));
+                }
+                try!(self.create(path, Content::File(Vec::new()), mode))
+            },
+            Err(e) => return Err(e)
+        };
+
+        let freed = match self.inodes[ino].content {
+            Content::Link(_) => return Err(errno::error(libc::ELOOP)),
+            Content::Dir(_) if flags.is_writable() => return Err(errno::error(libc::EISDIR)),
+            Content::File(_) if flags.contains(vfs::Directory) => return Err(errno::error(libc::ENOTDIR)),
+            Content::File(ref data) if flags.contains(vfs::Truncate) && flags.is_writable() => data.len() as u64,
+            _ => 0
+        };
+        if freed > 0 {
+            let inode = self.inodes.get_mut(&ino).unwrap();
+            inode.content = Content::File(Vec::new());
+            inode.modified = now_ms();
+            self.used_bytes -= freed;
+        }
+
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.open_files.insert(fd, OpenFile {
+            ino: ino,
+            pos: 0,
+            flags: flags
+        });
+        Ok(fd)
+    }
+
+    fn do_access(&self, path: &str) -> IoResult<()> {
+        self.lookup(path).map(|_| ())
+    }
+
+    fn do_stat(&self, path: &str) -> IoResult<FileStat> {
+        self.do_lstat(path)
+    }
+
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat> {
+        let ino = try!(self.lookup(path));
+        Ok(self.stat_inode(ino))
+    }
+
+    fn do_readlink(&self, path: &str) -> IoResult<String> {
+        let ino = try!(self.lookup(path));
+        match self.inodes[ino].content {
+            Content::Link(ref target) => Ok(target.clone()),
+            _ => Err(errno::error(libc::EINVAL))
+        }
+    }
+
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat> {
+        let ino = try!(self.get_open(handle)).ino;
+        Ok(self.stat_inode(ino))
+    }
+
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<vfs::DirEntry>> {
+        let ino = try!(self.get_open(handle)).ino;
+        match self.inodes[ino].content {
+            Content::Dir(ref entries) => Ok(entries.iter().map(|(name, child)| {
+                vfs::DirEntry {
+                    name: name.clone(),
+                    inode: *child,
+                    kind: self.inodes[*child].kind()
+                }
+            }).collect()),
+            _ => Err(errno::error(libc::ENOTDIR))
+        }
+    }
+
+    fn do_ftruncate(&mut self, handle: &io::Handle, len: u64) -> IoResult<()> {
+        let (ino, flags) = {
+            let f = try!(self.get_open(handle));
+            (f.ino, f.flags)
+        };
+        if !flags.is_writable() {
+            return Err(errno::error(libc::EINVAL));
+        }
+        self.truncate_inode(ino, len)
+    }
+
+    fn do_unlink(&mut self, path: &str) -> IoResult<()> {
+        if self.is_dir(try!(self.lookup(path))) {
+            return Err(errno::error(libc::EISDIR));
+        }
+        let ino = try!(self.unlink_entry(path));
+        self.release_inode(ino);
+        Ok(())
+    }
+
+    fn do_rmdir(&mut self, path: &str) -> IoResult<()> {
+        let ino = try!(self.lookup(path));
+        if ino == ROOT_INO {
+            return Err(errno::error(libc::EBUSY));
+        }
+        if !self.is_dir(ino) {
+            return Err(errno::error(libc::ENOTDIR));
+        }
+        if !self.is_empty_dir(ino) {
+            return Err(errno::error(libc::ENOTEMPTY));
+        }
+        try!(self.unlink_entry(path));
+        self.release_inode(ino);
+        Ok(())
+    }
+
+    fn do_mkdir(&mut self, path: &str, mode: u32) -> IoResult<()> {
+        self.create(path, Content::Dir(BTreeMap::new()), mode).map(|_| ())
+    }
+
+    fn do_rename(&mut self, from: &str, to: &str) -> IoResult<()> {
+        let ino = try!(self.lookup(from));
+        if ino == ROOT_INO {
+            return Err(errno::error(libc::EBUSY));
+        }
+        try!(self.lookup_parent(to));
+        if self.is_dir(ino) && to.starts_with(&format!("{}/", from)[]) {
+            return Err(errno::error(libc::EINVAL));
+        }
+        match self.lookup(to) {
+            // Both names already refer to the same file
+            Ok(existing) if existing == ino => return Ok(()),
+            Ok(existing) => {
+                match (self.is_dir(ino), self.is_dir(existing)) {
+                    (true, false) => return Err(errno::error(libc::ENOTDIR)),
+                    (false, true) => return Err(errno::error(libc::EISDIR)),
+                    (true, true) if !self.is_empty_dir(existing) => return Err(errno::error(libc::ENOTEMPTY)),
+                    _ => {}
+                }
+                try!(self.unlink_entry(to));
+                self.release_inode(existing);
+            },
+            Err(ref e) if errno::from_io_error(e) == libc::ENOENT => {},
+            Err(e) => return Err(e)
+        }
+        try!(self.unlink_entry(from));
+        self.link_entry(to, ino)
+    }
+
+    fn do_symlink(&mut self, target: &str, path: &str) -> IoResult<()> {
+        self.create_symlink(path, target)
+    }
+
+    fn do_link(&mut self, from: &str, to: &str) -> IoResult<()> {
+        let ino = try!(self.lookup(from));
+        if self.is_dir(ino) {
+            return Err(errno::error(libc::EPERM));
+        }
+        self.link_entry(to, ino)
+    }
+
+    fn do_chmod(&mut self, path: &str, mode: u32) -> IoResult<()> {
+        let ino = try!(self.lookup(path));
+        self.inodes.get_mut(&ino).unwrap().perm = mode & 0o7777;
+        Ok(())
+    }
+
+    fn do_truncate(&mut self, path: &str, len: u64) -> IoResult<()> {
+        let ino = try!(self.lookup(path));
+        self.truncate_inode(ino, len)
+    }
+
+    fn do_utimes(&mut self, path: &str, atime: u64, mtime: u64) -> IoResult<()> {
+        let ino = try!(self.lookup(path));
+        let inode = self.inodes.get_mut(&ino).unwrap();
+        inode.accessed = atime;
+        inode.modified = mtime;
+        Ok(())
+    }
+}
+
+impl io::Streaming for MemFS {
+    fn do_write(&mut self, handle: &io::Handle, buf: &[u8]) -> IoResult<usize> {
+        let (ino, pos, flags) = {
+            let f = try!(self.get_open(handle));
+            (f.ino, f.pos, f.flags)
+        };
+        if !flags.is_writable() {
+            return Err(errno::error(libc::EBADF));
+        }
+        let size = self.inodes[ino].size();
+        let start = if flags.contains(vfs::Append) { size } else { pos };
+        let end = match start.checked_add(buf.len() as u64) {
+            Some(end) if end <= self.max_file_size => end,
+            _ => return Err(errno::error(libc::EFBIG))
+        };
+        if end > size {
+            try!(self.reserve(end - size));
+        }
+
+        {
+            let inode = self.inodes.get_mut(&ino).unwrap();
+            match inode.content {
+                Content::File(ref mut data) => {
+                    if end as usize > data.len() {
+                        let grow = end as usize - data.len();
+                        data.extend(repeat(0u8).take(grow));
+                    }
+                    for (i, b) in buf.iter().enumerate() {
+                        data[start as usize + i] = *b;
+                    }
+                },
+                _ => return Err(errno::error(libc::EISDIR))
+            }
+            inode.modified = now_ms();
+        }
+
+        try!(self.get_open(handle)).pos = end;
+        Ok(buf.len())
+    }
+
+    fn do_read(&mut self, handle: &io::Handle, buf: &mut [u8]) -> IoResult<usize> {
+        let (ino, pos, flags) = {
+            let f = try!(self.get_open(handle));
+            (f.ino, f.pos, f.flags)
+        };
+        if !flags.is_readable() {
+            return Err(errno::error(libc::EBADF));
+        }
+        let len = match self.inodes[ino].content {
+            Content::File(ref data) => {
+                let start = cmp::min(pos as usize, data.len());
+                let len = cmp::min(buf.len(), data.len() - start);
+                for i in range(0, len) {
+                    buf[i] = data[start + i];
+                }
+                len
+            },
+            _ => return Err(errno::error(libc::EISDIR))
+        };
+        self.inodes.get_mut(&ino).unwrap().accessed = now_ms();
+        try!(self.get_open(handle)).pos = pos + len as u64;
+        Ok(len)
+    }
+
+    fn do_seek(&mut self, handle: &io::Handle, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        let (ino, pos) = {
+            let f = try!(self.get_open(handle));
+            (f.ino, f.pos)
+        };
+        let base = match whence {
+            SeekStyle::SeekSet => 0,
+            SeekStyle::SeekCur => pos as i64,
+            SeekStyle::SeekEnd => self.inodes[ino].size() as i64
+        };
+        let new_pos = match base.checked_add(offset) {
+            Some(p) if p < 0 => return Err(errno::error(libc::EINVAL)),
+            Some(p) => p as u64,
+            None => return Err(errno::error(libc::EOVERFLOW))
+        };
+        try!(self.get_open(handle)).pos = new_pos;
+        Ok(new_pos)
+    }
+
+    fn do_close(&mut self, handle: &io::Handle) -> IoResult<()> {
+        match self.open_files.remove(&handle.get_local_fd()) {
+            Some(f) => {
+                self.release_inode(f.ino);
+                Ok(())
+            },
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
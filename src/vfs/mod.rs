@@ -1,27 +1,118 @@
 extern crate seccomp;
+#[allow(unstable)]
+extern crate libc;
 
 use io;
 use events;
+use error::SandboxResult;
 use self::seccomp::Syscall;
-use std::collections::HashMap;
-use std::io::{IoResult, IoErrorKind, FileStat};
+use std::collections::{HashMap, BTreeSet};
+use std::io::{IoResult, IoError, IoErrorKind, FileStat, FileType, FilePermission, UnstableFileStat, SeekStyle};
+use std::iter::repeat;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::ptr;
+use std::cmp;
 
 pub mod native;
+pub mod path;
+pub mod proc;
+pub mod mem;
+pub mod tar;
+pub mod overlay;
+pub mod cas;
+pub mod errno;
+pub mod quota;
+pub mod mount;
+
+pub use self::quota::{Quota, Usage};
+
+/// The lowest fd number handed out for files opened through the VFS.
+///
+/// The seccomp policy traces fd-based syscalls at or above this number and
+/// lets everything below it through to the kernel.
+pub const FIRST_VIRTUAL_FD: i32 = 4098;
+
+bitflags! {
+    #[doc = "The flags argument of open(2), as understood by Filesystem::do_open"]
+    flags OpenFlags: i32 {
+        const ReadOnly = 0,
+        const WriteOnly = 0o1,
+        const ReadWrite = 0o2,
+        const Create = 0o100,
+        const Exclusive = 0o200,
+        const Truncate = 0o1000,
+        const Append = 0o2000,
+        const NonBlock = 0o4000,
+        const Directory = 0o200000,
+        const NoFollow = 0o400000,
+        const CloseExec = 0o2000000
+    }
+}
+
+impl OpenFlags {
+    pub fn is_readable(&self) -> bool {
+        self.bits & 0o3 != WriteOnly.bits
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.bits & 0o3 != ReadOnly.bits
+    }
+}
+
+/// Passed as the dirfd of the *at() syscalls to mean the current directory
+pub const AT_FDCWD: i32 = -100;
+const AT_SYMLINK_NOFOLLOW: u64 = 0x100;
+const AT_EMPTY_PATH: u64 = 0x1000;
+const AT_REMOVEDIR: u64 = 0x200;
+
+/// How many symlinks a single path lookup may traverse, matching the kernel
+const MAX_SYMLINKS: usize = 40;
+
+/// The most iovecs readv and writev accept, matching the kernel
+pub const IOV_MAX: usize = 1024;
+
+/// How many bytes of a count-byte read or write to copy
+pub fn transfer_len(count: u64) -> usize {
+    cmp::min(count, events::MAX_TRANSFER as u64) as usize
+}
+
+/// Cuts iovecs short so that together they cover at most MAX_TRANSFER bytes
+pub fn clamp_iovecs(iovecs: &[(u64, usize)]) -> Vec<(u64, usize)> {
+    let mut left = events::MAX_TRANSFER;
+    let mut clamped = Vec::new();
+    for &(base, len) in iovecs.iter() {
+        if left == 0 {
+            break;
+        }
+        let len = cmp::min(len, left);
+        clamped.push((base, len));
+        left -= len;
+    }
+    clamped
+}
 
 trait AsErrno {fn to_errno(&self) -> u64;}
 
-impl AsErrno for IoErrorKind {
+impl AsErrno for IoError {
+    /// The negative errno to return from the syscall that failed with self
     fn to_errno(&self) -> u64 {
-        -1
+        errno_ret(errno::from_io_error(self))
     }
 }
 
+fn errno_ret(errno: libc::c_int) -> u64 {
+    -(errno as i64) as u64
+}
+
 #[derive(Clone)]
 pub struct Handle<'fs> {
     _local_fd: i32,
     _virt_fd: i32,
+    _path: String,
+    _dir_offset: usize,
+    _listing: Option<Vec<DirEntry>>,
+    _flags: OpenFlags,
     _fs: Rc<RefCell<Box<Filesystem + 'fs>>>
 }
 
@@ -42,45 +133,261 @@ impl<'fs> io::Handle for Handle<'fs> {
         self._fs.borrow_mut().do_write(self, buf)
     }
 
+    fn seek(&mut self, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        self._fs.borrow_mut().do_seek(self, offset, whence)
+    }
+
     fn close(&mut self) -> IoResult<()> {
         self._fs.borrow_mut().do_close(self)
     }
 }
 
 impl<'fs> Handle<'fs> {
-    pub fn new(fs: Rc<RefCell<Box<Filesystem + 'fs>>>, virt_fd: i32, local_fd: i32) -> Self {
+    pub fn new(fs: Rc<RefCell<Box<Filesystem + 'fs>>>, virt_fd: i32, local_fd: i32, path: String) -> Self {
         Handle {
             _local_fd: local_fd,
             _virt_fd: virt_fd,
+            _path: path,
+            _dir_offset: 0,
+            _listing: None,
+            _flags: ReadOnly,
             _fs: fs
         }
     }
+
+    /// The absolute path within the VFS that this handle was opened with
+    pub fn get_path(&self) -> &str {
+        &self._path[]
+    }
+
+    /// The flags the file was opened with, if whoever opened it recorded them
+    pub fn get_flags(&self) -> OpenFlags {
+        self._flags
+    }
+
+    pub fn set_flags(&mut self, flags: OpenFlags) {
+        self._flags = flags;
+    }
+
+    pub fn stat(&self) -> IoResult<FileStat> {
+        self._fs.borrow_mut().do_fstat(self)
+    }
+
+    pub fn readdir(&self) -> IoResult<Vec<DirEntry>> {
+        self._fs.borrow_mut().do_readdir(self)
+    }
+
+    pub fn truncate(&self, len: u64) -> IoResult<()> {
+        self._fs.borrow_mut().do_ftruncate(self, len)
+    }
+
+    pub fn sync(&self, data_only: bool) -> IoResult<()> {
+        self._fs.borrow_mut().do_fsync(self, data_only)
+    }
+}
+
+/// A single entry in a directory listing, not including "." or ".."
+#[derive(Show, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub kind: FileType
+}
+
+/// Builds a FileStat for filesystems whose files don't exist on the host
+pub fn synthetic_stat(kind: FileType, perm: u32, size: u64, inode: u64) -> FileStat {
+    FileStat {
+        size: size,
+        kind: kind,
+        perm: FilePermission::from_bits_truncate(perm),
+        created: 0,
+        modified: 0,
+        accessed: 0,
+        unstable: UnstableFileStat {
+            device: 0,
+            inode: inode,
+            rdev: 0,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            blksize: 4096,
+            blocks: (size + 511) / 512,
+            flags: 0,
+            gen: 0
+        }
+    }
+}
+
+/// Hands out virtual fd numbers starting at FIRST_VIRTUAL_FD, reusing the
+/// lowest closed number first, the same way the kernel does.
+pub struct FdAllocator {
+    next: i32,
+    free: BTreeSet<i32>
+}
+
+impl FdAllocator {
+    pub fn new() -> FdAllocator {
+        FdAllocator {
+            next: FIRST_VIRTUAL_FD,
+            free: BTreeSet::new()
+        }
+    }
+
+    pub fn allocate(&mut self) -> i32 {
+        match self.free.iter().next().map(|fd| *fd) {
+            Some(fd) => {
+                self.free.remove(&fd);
+                fd
+            },
+            None => {
+                let fd = self.next;
+                self.next += 1;
+                fd
+            }
+        }
+    }
+
+    pub fn release(&mut self, fd: i32) {
+        if fd >= FIRST_VIRTUAL_FD && fd < self.next {
+            self.free.insert(fd);
+        }
+    }
 }
 
 pub trait Filesystem: io::Streaming {
-    fn do_open(&mut self, path: &str, flags: i32, mode: i32) -> IoResult<i32>;
+    fn do_open(&mut self, path: &str, flags: OpenFlags, mode: u32) -> IoResult<i32>;
     fn do_access(&self, path: &str) -> IoResult<()>;
     fn do_stat(&self, path: &str) -> IoResult<FileStat>;
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat>;
+    fn do_readlink(&self, path: &str) -> IoResult<String>;
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat>;
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<DirEntry>>;
+
+    /// Sets the size of the open file, which filesystems that can't be
+    /// written to don't need to support
+    fn do_ftruncate(&mut self, _handle: &io::Handle, _len: u64) -> IoResult<()> {
+        Err(errno::error(libc::EINVAL))
+    }
+
+    /// Flushes the open file to storage. Filesystems without any storage
+    /// behind them have nothing to do.
+    fn do_fsync(&mut self, _handle: &io::Handle, _data_only: bool) -> IoResult<()> {
+        Ok(())
+    }
+
+    /// Filesystems that can never be changed return true, and the VFS
+    /// fails anything that would modify them with EROFS without asking.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    // The operations below change the filesystem's tree. Backends that
+    // don't support one can leave it refusing with EPERM.
+
+    fn do_unlink(&mut self, _path: &str) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    fn do_rmdir(&mut self, _path: &str) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    fn do_mkdir(&mut self, _path: &str, _mode: u32) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    /// Both paths are on this filesystem; the VFS refuses renames across
+    /// mounts with EXDEV.
+    fn do_rename(&mut self, _from: &str, _to: &str) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    /// Creates a symlink at path. The target is stored as given and is
+    /// resolved by the VFS, never by the host.
+    fn do_symlink(&mut self, _target: &str, _path: &str) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    fn do_link(&mut self, _from: &str, _to: &str) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    fn do_chmod(&mut self, _path: &str, _mode: u32) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    fn do_truncate(&mut self, _path: &str, _len: u64) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
+
+    /// Times are in milliseconds, like those in FileStat
+    fn do_utimes(&mut self, _path: &str, _atime: u64, _mtime: u64) -> IoResult<()> {
+        Err(errno::error(libc::EPERM))
+    }
 }
 
 pub type FsRef<'fs> = Rc<RefCell<Box<Filesystem + 'fs>>>;
 
 pub struct VFS<'fs> {
-    filesystems: HashMap<String, FsRef<'fs>>,
+    mounts: mount::MountTable<'fs>,
     cwd: String,
-    next_fd: i32,
+    fds: FdAllocator,
     open_fds: HashMap<i32, Handle<'fs>>,
-    whitelist: Vec<String>
+    quota: Quota,
+    usage: Usage,
+    whitelist: Vec<String>,
+    proc_info: proc::ProcInfoRef,
+    umask: u32
 }
 
 impl<'fs> events::SyscallHandler for VFS<'fs> {
-    fn handle_syscall(&mut self, call: &mut events::Syscall) {
+    fn handle_syscall(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        self.proc_info.borrow_mut().pid = call.pid;
         match call.symbolic {
             Syscall::ACCESS => self.do_access(call),
-            Syscall::OPEN => self.do_open(call),
+            Syscall::OPEN => self.do_open(call, None, 0),
+            Syscall::OPENAT => self.do_open(call, Some(0), 1),
+            Syscall::NEWFSTATAT => self.do_newfstatat(call),
+            Syscall::FACCESSAT => self.do_faccessat(call),
+            Syscall::READLINK => self.do_readlink(call, None, 0),
+            Syscall::READLINKAT => self.do_readlink(call, Some(0), 1),
+            Syscall::CHDIR => self.do_chdir(call),
+            Syscall::FCHDIR => self.do_fchdir(call),
+            Syscall::GETCWD => self.do_getcwd(call),
             Syscall::STAT => self.do_stat(call),
+            Syscall::LSTAT => self.do_lstat(call),
+            Syscall::READ => self.do_read(call),
+            Syscall::WRITE => self.do_write(call),
+            Syscall::READV => self.do_readv(call),
+            Syscall::WRITEV => self.do_writev(call),
+            Syscall::LSEEK => self.do_lseek(call),
+            Syscall::FSTAT => self.do_fstat(call),
+            Syscall::CLOSE => self.do_close(call),
+            Syscall::FTRUNCATE => self.do_ftruncate(call),
+            Syscall::FSYNC => self.do_fsync(call, false),
+            Syscall::FDATASYNC => self.do_fsync(call, true),
+            Syscall::UNLINK => self.do_unlink(call, None, 0, 0),
+            Syscall::UNLINKAT => {
+                let flags = call.call.args[2];
+                self.do_unlink(call, Some(0), 1, flags)
+            },
+            Syscall::RMDIR => self.do_unlink(call, None, 0, AT_REMOVEDIR),
+            Syscall::MKDIR => self.do_mkdir(call, None, 0),
+            Syscall::MKDIRAT => self.do_mkdir(call, Some(0), 1),
+            Syscall::RENAME => self.do_rename(call, (None, 0), (None, 1)),
+            Syscall::RENAMEAT => self.do_rename(call, (Some(0), 1), (Some(2), 3)),
+            Syscall::LINK => self.do_link(call),
+            Syscall::SYMLINK => self.do_symlink(call),
+            Syscall::CHMOD => self.do_chmod(call),
+            Syscall::UMASK => self.do_umask(call),
+            Syscall::TRUNCATE => self.do_truncate(call),
+            Syscall::UTIMES => self.do_utimes(call),
+            Syscall::GETDENTS => self.do_getdents(call, false),
+            Syscall::GETDENTS64 => self.do_getdents(call, true),
+            Syscall::IOCTL => call.finish(errno_ret(libc::ENOTTY)),
             _ => {
                 println!("Got unhandled syscall {:?}", call);
+                call.finish(errno_ret(libc::ENOSYS))
             }
         }
     }
@@ -88,10 +395,70 @@ impl<'fs> events::SyscallHandler for VFS<'fs> {
 
 impl<'fs> VFS<'fs> {
 
-    fn with_filename_arg<T>(&self, call: &mut events::Syscall, arg_num: usize, f: &mut FnMut(&mut events::Syscall, String, &FsRef<'fs>) -> Option<T>) -> Option<T> {
-        match self.get_filesystem_from_arg(call, arg_num) {
-            None => {call.finish(2);None},
-            Some((path, fs)) => f(call, path, fs)
+    fn with_filename_arg<T>(&self, call: &mut events::Syscall, arg_num: usize, follow_links: bool, f: &mut FnMut(&mut events::Syscall, String, String, &FsRef<'fs>) -> SandboxResult<T>) -> SandboxResult<Option<T>> {
+        self.with_path_at(call, None, arg_num, follow_links, f)
+    }
+
+    /// Resolves the path in arg_num, relative to the directory fd in
+    /// dirfd_arg if one is given, and hands f the absolute virtual path,
+    /// the path local to its filesystem, and the filesystem itself.
+    ///
+    /// None if the call was finished here instead, because the path was
+    /// whitelisted or couldn't be resolved.
+    fn with_path_at<T>(&self, call: &mut events::Syscall, dirfd_arg: Option<usize>, arg_num: usize, follow_links: bool, f: &mut FnMut(&mut events::Syscall, String, String, &FsRef<'fs>) -> SandboxResult<T>) -> SandboxResult<Option<T>> {
+        let fname = match read_path_arg(call, arg_num) {
+            Ok(fname) => fname,
+            Err(err) => return call.finish(err.to_errno()).map(|_| None)
+        };
+        if self.is_whitelisted(&fname) {
+            return call.finish_default().map(|_| None);
+        }
+        let dirfd = match dirfd_arg {
+            Some(a) => call.call.args[a] as i32,
+            None => AT_FDCWD
+        };
+        let res = self.path_relative_to(dirfd, &fname[]).and_then(|p| {
+            self.get_filesystem(&p[], follow_links)
+        });
+        match res {
+            Err(err) => call.finish(err.to_errno()).map(|_| None),
+            Ok((abs_path, path, fs)) => f(call, abs_path, path, fs).map(Some)
+        }
+    }
+
+    /// Resolves the path in arg_num for a syscall that changes the tree.
+    ///
+    /// Unlike with_path_at, whitelisted paths get no special treatment: they
+    /// are there so the host's libraries can be loaded, not modified.
+    fn mutable_path_at(&self, call: &events::Syscall, dirfd_arg: Option<usize>, arg_num: usize, follow_links: bool) -> IoResult<(String, &FsRef<'fs>)> {
+        let fname = try!(read_path_arg(call, arg_num));
+        let dirfd = match dirfd_arg {
+            Some(a) => call.call.args[a] as i32,
+            None => AT_FDCWD
+        };
+        let p = try!(self.path_relative_to(dirfd, &fname[]));
+        let (_, local, fs) = try!(self.get_filesystem(&p[], follow_links));
+        if fs.borrow().is_read_only() {
+            return Err(errno::error(libc::EROFS));
+        }
+        Ok((local, fs))
+    }
+
+    /// Joins a relative path onto the directory that dirfd refers to, the
+    /// way the *at() family of syscalls does. Any ".." is left for resolve,
+    /// which knows where links lead.
+    fn path_relative_to(&self, dirfd: i32, path: &str) -> IoResult<String> {
+        if path.starts_with("/") {
+            return Ok(String::from_str(path));
+        }
+        if dirfd == AT_FDCWD {
+            return Ok(self.cwd.clone() + "/" + path);
+        }
+        match self.open_fds.get(&dirfd) {
+            Some(h) => Ok(String::from_str(h.get_path()) + "/" + path),
+            // We can't know where a host fd points, and looking anything up
+            // relative to it would bypass the VFS.
+            None => Err(errno::error(libc::EBADF))
         }
     }
 
@@ -104,107 +471,805 @@ impl<'fs> VFS<'fs> {
         false
     }
 
-    fn get_filesystem_from_arg(&self, call: &events::Syscall, argnum: usize) -> Option<(String, &FsRef<'fs>)> {
-        let fname = call.read_string_arg(argnum);
-        if !self.is_whitelisted(&fname) {
-            self.get_filesystem(&*fname)
-        } else {
-            None
+    fn get_filesystem(&self, path: &str, follow_links: bool) -> IoResult<(String, String, &FsRef<'fs>)> {
+        let abs_path = try!(self.resolve(path, follow_links));
+        match self.mounts.resolve(&abs_path[]) {
+            Some((local, fs)) => Ok((abs_path, local, fs)),
+            None => Err(errno::error(libc::ENOENT))
         }
     }
 
-    fn get_filesystem(&self, path: &str) -> Option<(String, &FsRef<'fs>)> {
-        //FIXME: Search for longest mount point instead of first match
-        let abs_path;
-        if path.chars().next() == Some('.') {
-            abs_path = self.cwd.clone() + path;
+    /// Turns path into an absolute virtual path with every symlink along
+    /// the way replaced by its target.
+    ///
+    /// Link targets are read from whichever filesystem holds the link and
+    /// are interpreted inside the VFS, so an absolute target goes through
+    /// mount lookup again rather than pointing at the host. As in the
+    /// kernel, ".." leaves the directory a link led to rather than the one
+    /// the link is in. The final component is only followed when
+    /// follow_last is set, as with lstat.
+    pub fn resolve(&self, path: &str, follow_last: bool) -> IoResult<String> {
+        let full = if path.starts_with("/") {
+            String::from_str(path)
         } else {
-            abs_path = String::from_str(path);
-        }
+            self.cwd.clone() + "/" + path
+        };
+        // Components left to walk, the next one last
+        let mut pending: Vec<String> = full.split('/').rev().map(|c| String::from_str(c)).collect();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut links = 0;
 
-        for (mount_point, fs) in self.filesystems.iter() {
-            if abs_path.starts_with(&mount_point[]) {
-                let fs_local_path = abs_path.slice_from(mount_point.len());
-                return Some((String::from_str(fs_local_path), fs));
+        loop {
+            let part = match pending.pop() {
+                Some(part) => part,
+                None => break
+            };
+            match &part[] {
+                "" | "." => continue,
+                ".." => {
+                    resolved.pop();
+                    continue;
+                },
+                _ => {}
+            }
+            resolved.push(part);
+            let is_last = pending.iter().all(|c| c.is_empty() || &c[] == ".");
+            if is_last && !follow_last {
+                continue;
             }
-        }
 
-        return None;
+            let prefix = path::join(&resolved.iter().map(|c| &c[]).collect::<Vec<&str>>()[]);
+            let target = match self.mounts.resolve(&prefix[]) {
+                None => continue,
+                Some((local, fs)) => {
+                    let fs = fs.borrow();
+                    match fs.do_lstat(&local[]) {
+                        Ok(ref st) if st.kind == FileType::Symlink => try!(fs.do_readlink(&local[])),
+                        // Anything else is left for the actual operation to report
+                        _ => continue
+                    }
+                }
+            };
+
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(errno::error(libc::ELOOP));
+            }
+
+            resolved.pop();
+            if target.starts_with("/") {
+                resolved.clear();
+            }
+            pending.extend(target.split('/').rev().map(|c| String::from_str(c)));
+        }
+        Ok(path::join(&resolved.iter().map(|c| &c[]).collect::<Vec<&str>>()[]))
     }
 
     pub fn mount_filesystem(&mut self, mount_point: &str, fs: Box<Filesystem + 'fs>) {
-        self.filesystems.insert(String::from_str(mount_point), Rc::new(RefCell::new(fs)));
+        self.mounts.mount(mount_point, Rc::new(RefCell::new(fs)));
+    }
+
+    /// The sandbox's current directory, as seen from inside the VFS
+    pub fn get_cwd(&self) -> &str {
+        &self.cwd[]
+    }
+
+    /// Sets the current directory without checking that it exists, e.g. to
+    /// start the sandbox somewhere other than /
+    pub fn set_cwd(&mut self, cwd: &str) {
+        self.cwd = path::normalize("/", cwd);
+        self.proc_info.borrow_mut().cwd = self.cwd.clone();
+    }
+
+    /// Sets the mask taken off the mode of files and directories the sandbox
+    /// creates, which it can change with umask(2). 0o022 to begin with.
+    pub fn set_umask(&mut self, mask: u32) {
+        self.umask = mask & 0o777;
+    }
+
+    pub fn get_umask(&self) -> u32 {
+        self.umask
+    }
+
+    /// Sets the path that /proc/self/exe points to
+    pub fn set_exe(&mut self, exe: &str) {
+        self.proc_info.borrow_mut().exe = path::normalize("/", exe);
+    }
+
+    /// Mounts a ProcFS that reports this VFS's view of the sandbox
+    pub fn mount_procfs(&mut self, mount_point: &str) {
+        let fs = proc::ProcFS::new(self.proc_info.clone());
+        self.mount_filesystem(mount_point, Box::new(fs));
+    }
+
+    pub fn unmount_filesystem(&mut self, mount_point: &str) -> Option<FsRef<'fs>> {
+        self.mounts.unmount(mount_point)
+    }
+
+    pub fn get_quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Limits what the sandbox can store from here on. Usage so far still
+    /// counts against the new limits.
+    pub fn set_quota(&mut self, quota: Quota) {
+        self.quota = quota;
     }
 
-    fn do_stat(&self, call: &mut events::Syscall) {
-        self.with_filename_arg(call, 0, &mut |call, path, fs| {
-            Some(match fs.borrow_mut().do_stat(&path[]) {
+    /// What the sandbox has stored and has open through the VFS
+    pub fn get_usage(&self) -> Usage {
+        Usage {
+            open_files: self.open_fds.len(),
+            ..self.usage
+        }
+    }
+
+    fn do_stat(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        self.with_filename_arg(call, 0, true, &mut |call, _, path, fs| {
+            match fs.borrow_mut().do_stat(&path[]) {
                 Ok(sbuf) => {
-                    call.write_buf_arg(0, &Stat {
-                        st_dev: sbuf.unstable.device,
-                        st_ino: sbuf.unstable.inode,
-                        st_mode: sbuf.perm.bits() as i64,
-                        st_nlink: sbuf.unstable.nlink,
-                        st_uid: sbuf.unstable.uid as i64,
-                        st_gid: sbuf.unstable.gid as i64,
-                        st_rdev: sbuf.unstable.rdev,
-                        st_size: sbuf.size as i64,
-                        st_blksize: sbuf.unstable.blksize as i64,
-                        st_blocks: sbuf.unstable.blocks as i64,
-                        st_atime: sbuf.accessed,
-                        st_atime_nsec: 0,
-                        st_mtime: sbuf.modified,
-                        st_mtime_nsec: 0,
-                        st_ctime: sbuf.created,
-                        st_ctime_nsec: 0,
-                        __pad0: 0,
-                        __pad1: 0,
-                        __pad2: 0,
-                        __pad3: 0
-                    });
+                    call.write_buf_arg(1, &Stat::from_file_stat(&sbuf));
                     call.finish(0)
                 },
-                Err(err) => call.finish(err.kind.to_errno())
-            })
-        });
+                Err(err) => call.finish(err.to_errno())
+            }
+        }).map(|_| ())
+    }
+
+    fn do_lstat(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        self.with_filename_arg(call, 0, false, &mut |call, _, path, fs| {
+            match fs.borrow_mut().do_lstat(&path[]) {
+                Ok(sbuf) => {
+                    call.write_buf_arg(1, &Stat::from_file_stat(&sbuf));
+                    call.finish(0)
+                },
+                Err(err) => call.finish(err.to_errno())
+            }
+        }).map(|_| ())
     }
 
-    fn do_access(&self, call: &mut events::Syscall) {
-        self.with_filename_arg(call, 0, &mut |call, path, fs| {
-            Some(match fs.borrow_mut().do_access(&path[]) {
+    fn do_access(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        self.with_filename_arg(call, 0, true, &mut |call, _, path, fs| {
+            match fs.borrow_mut().do_access(&path[]) {
                 Ok(_) => call.finish(0),
-                Err(err) => call.finish(err.kind.to_errno())
-            })
+                Err(err) => call.finish(err.to_errno())
+            }
+        }).map(|_| ())
+    }
+
+    fn do_newfstatat(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let at_flags = call.call.args[3];
+        if at_flags & AT_EMPTY_PATH != 0 && call.read_string_arg(1).map(|p| p.is_empty()) == Ok(true) {
+            let res = match self.open_fds.get(&(call.call.args[0] as i32)) {
+                // An empty path on a host fd only ever refers to the fd itself
+                None => return call.finish_default(),
+                Some(h) => h.stat()
+            };
+            return match res {
+                Ok(sbuf) => {
+                    call.write_buf_arg(2, &Stat::from_file_stat(&sbuf));
+                    call.finish(0)
+                },
+                Err(err) => call.finish(err.to_errno())
+            };
+        }
+
+        let follow = at_flags & AT_SYMLINK_NOFOLLOW == 0;
+        self.with_path_at(call, Some(0), 1, follow, &mut |call, _, path, fs| {
+            let res = if follow {
+                fs.borrow_mut().do_stat(&path[])
+            } else {
+                fs.borrow_mut().do_lstat(&path[])
+            };
+            match res {
+                Ok(sbuf) => {
+                    call.write_buf_arg(2, &Stat::from_file_stat(&sbuf));
+                    call.finish(0)
+                },
+                Err(err) => call.finish(err.to_errno())
+            }
+        }).map(|_| ())
+    }
+
+    fn do_faccessat(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        self.with_path_at(call, Some(0), 1, true, &mut |call, _, path, fs| {
+            match fs.borrow_mut().do_access(&path[]) {
+                Ok(_) => call.finish(0),
+                Err(err) => call.finish(err.to_errno())
+            }
+        }).map(|_| ())
+    }
+
+    /// Shared by open and openat, whose flags and mode follow the path
+    fn do_open(&mut self, call: &mut events::Syscall, dirfd_arg: Option<usize>, path_arg: usize) -> SandboxResult<()> {
+        let fname = match read_path_arg(call, path_arg) {
+            Ok(fname) => fname,
+            Err(err) => return call.finish(err.to_errno())
+        };
+        if self.is_whitelisted(&fname) {
+            return call.finish_default();
+        }
+        let dirfd = match dirfd_arg {
+            Some(a) => call.call.args[a] as i32,
+            None => AT_FDCWD
+        };
+        let flags = OpenFlags::from_bits_truncate(call.call.args[path_arg + 1] as i32);
+        let mode = (call.call.args[path_arg + 2] & 0o7777) as u32;
+        match self.open(dirfd, &fname[], flags, mode) {
+            Ok(fd) => call.finish(fd as u64),
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    /// Opens path, relative to dirfd unless it's absolute, the way openat
+    /// does for the sandbox. Returns the new virtual fd.
+    pub fn open(&mut self, dirfd: i32, path: &str, flags: OpenFlags, mode: u32) -> IoResult<i32> {
+        // Like the kernel, don't follow a trailing symlink when asked not to
+        // or when the file must be newly created.
+        let follow = !(flags.contains(NoFollow) || flags.contains(Create | Exclusive));
+        let p = try!(self.path_relative_to(dirfd, path));
+        let (abs_path, local, fs) = {
+            let (abs_path, local, fs) = try!(self.get_filesystem(&p[], follow));
+            (abs_path, local, fs.clone())
+        };
+        let existing = fs.borrow().do_lstat(&local[]).ok();
+        let creating = existing.is_none() && flags.contains(Create);
+        try!(self.quota.check_open_files(self.open_fds.len()));
+        if creating {
+            try!(self.quota.check_inodes(&self.usage));
+        }
+        let local_fd = try!(fs.borrow_mut().do_open(&local[], flags, mode & !self.umask));
+
+        if creating {
+            self.usage.add_inode();
+        }
+        match existing {
+            Some(ref st) if flags.contains(Truncate) && flags.is_writable() => self.usage.free_bytes(st.size),
+            _ => {}
+        }
+        let fd_num = self.fds.allocate();
+        let mut h = Handle::new(fs, fd_num, local_fd, abs_path.clone());
+        h.set_flags(flags);
+        self.proc_info.borrow_mut().fds.insert(fd_num, abs_path);
+        self.open_fds.insert(fd_num, h);
+        Ok(fd_num)
+    }
+
+    /// The handle behind a virtual fd
+    pub fn get_handle(&mut self, fd: i32) -> Option<&mut Handle<'fs>> {
+        self.open_fds.get_mut(&fd)
+    }
+
+    fn do_readlink(&self, call: &mut events::Syscall, dirfd_arg: Option<usize>, path_arg: usize) -> SandboxResult<()> {
+        // bufsiz is an int, and the kernel refuses it before looking at path
+        if call.call.args[path_arg + 2] as i32 <= 0 {
+            return call.finish(errno_ret(libc::EINVAL));
+        }
+        self.with_path_at(call, dirfd_arg, path_arg, false, &mut |call, _, path, fs| {
+            match fs.borrow_mut().do_readlink(&path[]) {
+                Ok(target) => {
+                    let bufsiz = call.call.args[path_arg + 2] as usize;
+                    let bytes = target.as_bytes();
+                    let len = if bytes.len() < bufsiz { bytes.len() } else { bufsiz };
+                    match call.write_bytes(call.call.args[path_arg + 1], &bytes[..len]) {
+                        Ok(_) => call.finish(len as u64),
+                        Err(_) => call.finish(errno_ret(libc::EFAULT))
+                    }
+                },
+                Err(err) => call.finish(err.to_errno())
+            }
+        }).map(|_| ())
+    }
+
+    /// Handles unlink, unlinkat and rmdir, which share everything but which
+    /// kind of file they remove
+    fn do_unlink(&mut self, call: &mut events::Syscall, dirfd_arg: Option<usize>, path_arg: usize, flags: u64) -> SandboxResult<()> {
+        let res = self.mutable_path_at(call, dirfd_arg, path_arg, false).and_then(|(path, fs)| {
+            let st = try!(fs.borrow().do_lstat(&path[]));
+            if flags & AT_REMOVEDIR != 0 {
+                try!(fs.borrow_mut().do_rmdir(&path[]));
+            } else {
+                try!(fs.borrow_mut().do_unlink(&path[]));
+            }
+            Ok(st)
+        });
+        match res {
+            Ok(ref st) => {
+                self.usage.unlinked(st);
+                call.finish(0)
+            },
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_mkdir(&mut self, call: &mut events::Syscall, dirfd_arg: Option<usize>, path_arg: usize) -> SandboxResult<()> {
+        let mode = (call.call.args[path_arg + 1] & 0o7777) as u32 & !self.umask;
+        let res = self.mutable_path_at(call, dirfd_arg, path_arg, false).and_then(|(path, fs)| {
+            try!(self.quota.check_inodes(&self.usage));
+            fs.borrow_mut().do_mkdir(&path[], mode)
+        });
+        if res.is_ok() {
+            self.usage.add_inode();
+        }
+        finish_unit(call, res)
+    }
+
+    /// Resolves both paths of a rename or link, which must end up on the
+    /// same filesystem
+    fn path_pair(&self, call: &events::Syscall, from: (Option<usize>, usize), to: (Option<usize>, usize)) -> IoResult<(String, String, &FsRef<'fs>)> {
+        let (from_path, from_fs) = try!(self.mutable_path_at(call, from.0, from.1, false));
+        let (to_path, to_fs) = try!(self.mutable_path_at(call, to.0, to.1, false));
+        if &**from_fs as *const RefCell<Box<Filesystem + 'fs>> != &**to_fs as *const RefCell<Box<Filesystem + 'fs>> {
+            return Err(errno::error(libc::EXDEV));
+        }
+        Ok((from_path, to_path, from_fs))
+    }
+
+    fn do_rename(&mut self, call: &mut events::Syscall, from: (Option<usize>, usize), to: (Option<usize>, usize)) -> SandboxResult<()> {
+        let res = self.path_pair(call, from, to).and_then(|(from, to, fs)| {
+            // Renaming over a file removes it
+            let replaced = fs.borrow().do_lstat(&to[]).ok();
+            try!(fs.borrow_mut().do_rename(&from[], &to[]));
+            Ok(replaced)
+        });
+        match res {
+            Ok(replaced) => {
+                match replaced {
+                    Some(ref st) => self.usage.unlinked(st),
+                    None => {}
+                }
+                call.finish(0)
+            },
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_link(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let res = self.path_pair(call, (None, 0), (None, 1)).and_then(|(from, to, fs)| {
+            fs.borrow_mut().do_link(&from[], &to[])
+        });
+        finish_unit(call, res)
+    }
+
+    fn do_symlink(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let target = match read_path_arg(call, 0) {
+            Ok(target) => target,
+            Err(err) => return call.finish(err.to_errno())
+        };
+        let res = self.mutable_path_at(call, None, 1, false).and_then(|(path, fs)| {
+            try!(self.quota.check_inodes(&self.usage));
+            fs.borrow_mut().do_symlink(&target[], &path[])
+        });
+        if res.is_ok() {
+            self.usage.add_inode();
+        }
+        finish_unit(call, res)
+    }
+
+    fn do_chmod(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let mode = (call.call.args[1] & 0o7777) as u32;
+        let res = self.mutable_path_at(call, None, 0, true).and_then(|(path, fs)| {
+            fs.borrow_mut().do_chmod(&path[], mode)
+        });
+        finish_unit(call, res)
+    }
+
+    /// umask can't fail, and returns the mask it replaces
+    fn do_umask(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let old = self.umask;
+        self.set_umask(call.call.args[0] as u32);
+        call.finish(old as u64)
+    }
+
+    fn do_truncate(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        if (call.call.args[1] as i64) < 0 {
+            return call.finish(errno_ret(libc::EINVAL));
+        }
+        let len = call.call.args[1];
+        let res = self.mutable_path_at(call, None, 0, true).and_then(|(path, fs)| {
+            let before = try!(fs.borrow().do_stat(&path[])).size;
+            if len > before {
+                try!(self.quota.check_bytes(&self.usage, len - before));
+            }
+            try!(fs.borrow_mut().do_truncate(&path[], len));
+            Ok(before)
+        });
+        match res {
+            Ok(before) => {
+                self.usage.resize(before, len);
+                call.finish(0)
+            },
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_utimes(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        // A NULL times argument means now, otherwise it points at two
+        // struct timevals: the access time, then the modification time.
+        let times = if call.call.args[1] == 0 {
+            let now = unsafe { libc::time(ptr::null_mut()) } as u64 * 1000;
+            (now, now)
+        } else {
+            match call.read_bytes(call.call.args[1], 32) {
+                Ok(buf) => (timeval_ms(&buf[0..16]), timeval_ms(&buf[16..32])),
+                Err(_) => return call.finish(errno_ret(libc::EFAULT))
+            }
+        };
+        let res = self.mutable_path_at(call, None, 0, true).and_then(|(path, fs)| {
+            fs.borrow_mut().do_utimes(&path[], times.0, times.1)
         });
+        finish_unit(call, res)
+    }
+
+    fn do_chdir(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let dir = try!(self.with_filename_arg(call, 0, true, &mut |call, abs_path, path, fs| {
+            match fs.borrow_mut().do_stat(&path[]) {
+                Ok(ref st) if st.kind == FileType::Directory => Ok(Some(abs_path)),
+                Ok(_) => call.finish(errno_ret(libc::ENOTDIR)).map(|_| None),
+                Err(err) => call.finish(err.to_errno()).map(|_| None)
+            }
+        }));
+        match dir {
+            Some(Some(abs_path)) => {
+                self.set_cwd(&abs_path[]);
+                call.finish(0)
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn do_fchdir(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let res = match self.open_fds.get(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h.stat().map(|st| (st, String::from_str(h.get_path())))
+        };
+        match res {
+            Ok((ref st, ref abs_path)) if st.kind == FileType::Directory => {
+                self.set_cwd(&abs_path[]);
+                call.finish(0)
+            },
+            Ok(_) => call.finish(errno_ret(libc::ENOTDIR)),
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_getcwd(&self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let mut buf = self.cwd.clone().into_bytes();
+        buf.push(0);
+        if (call.call.args[1] as usize) < buf.len() {
+            return call.finish(errno_ret(libc::ERANGE));
+        }
+        match call.write_bytes(call.call.args[0], &buf[]) {
+            Ok(_) => call.finish(buf.len() as u64),
+            Err(_) => call.finish(errno_ret(libc::EFAULT))
+        }
+    }
+
+    fn do_read(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let mut buf: Vec<u8> = repeat(0u8).take(transfer_len(call.call.args[2])).collect();
+        let res = match self.open_fds.get_mut(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => (h as &mut io::Handle).read(&mut buf[])
+        };
+        match res {
+            Ok(len) => match call.write_bytes(call.call.args[1], &buf[..len]) {
+                Ok(_) => call.finish(len as u64),
+                Err(_) => call.finish(errno_ret(libc::EFAULT))
+            },
+            Err(ref err) if err.kind == IoErrorKind::EndOfFile => call.finish(0),
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_write(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let buf = match call.read_bytes(call.call.args[1], transfer_len(call.call.args[2])) {
+            Ok(b) => b,
+            Err(_) => return call.finish(errno_ret(libc::EFAULT))
+        };
+        let res = match self.open_fds.get_mut(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => VFS::write_within_quota(&self.quota, &mut self.usage, h, &buf[])
+        };
+        match res {
+            Ok(len) => call.finish(len as u64),
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    /// Writes buf to h, failing with EDQUOT rather than letting the file
+    /// grow past the byte quota
+    fn write_within_quota(quota: &Quota, usage: &mut Usage, h: &mut Handle<'fs>, buf: &[u8]) -> IoResult<usize> {
+        let before = try!(h.stat()).size;
+        let start = if h.get_flags().contains(Append) {
+            before
+        } else {
+            try!((h as &mut io::Handle).seek(0, SeekStyle::SeekCur))
+        };
+        let end = start + buf.len() as u64;
+        if end > before {
+            try!(quota.check_bytes(usage, end - before));
+        }
+        let res = (h as &mut io::Handle).write(buf);
+        match h.stat() {
+            Ok(st) => usage.resize(before, st.size),
+            Err(_) => {}
+        }
+        res
+    }
+
+    /// Reads the iovec array of readv or writev, clamped to MAX_TRANSFER
+    /// bytes in all. The Err is the errno to fail the call with.
+    fn read_iovecs(call: &events::Syscall) -> Result<Vec<(u64, usize)>, libc::c_int> {
+        if call.call.args[2] > IOV_MAX as u64 {
+            return Err(libc::EINVAL);
+        }
+        let count = call.call.args[2] as usize;
+        let raw = match call.read_bytes(call.call.args[1], count * 16) {
+            Ok(r) => r,
+            Err(_) => return Err(libc::EFAULT)
+        };
+        let mut iovecs = Vec::with_capacity(count);
+        for i in range(0, count) {
+            let base = read_u64(&raw[i * 16..i * 16 + 8]);
+            let len = read_u64(&raw[i * 16 + 8..i * 16 + 16]);
+            iovecs.push((base, len as usize));
+        }
+        Ok(clamp_iovecs(&iovecs[]))
+    }
+
+    fn do_readv(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let iovecs = match VFS::read_iovecs(call) {
+            Ok(v) => v,
+            Err(e) => return call.finish(errno_ret(e))
+        };
+        let h = match self.open_fds.get_mut(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h as &mut io::Handle
+        };
+        let mut total = 0;
+        for &(base, len) in iovecs.iter() {
+            let mut buf: Vec<u8> = repeat(0u8).take(len).collect();
+            match h.read(&mut buf[]) {
+                Ok(n) => {
+                    if call.write_bytes(base, &buf[..n]).is_err() {
+                        return call.finish(errno_ret(libc::EFAULT));
+                    }
+                    total += n;
+                    if n < len {
+                        break;
+                    }
+                },
+                Err(ref err) if err.kind == IoErrorKind::EndOfFile => break,
+                Err(err) => {
+                    if total == 0 {
+                        return call.finish(err.to_errno());
+                    }
+                    break;
+                }
+            }
+        }
+        call.finish(total as u64)
     }
 
-    fn do_open(&mut self, call: &mut events::Syscall) {
-        match self.with_filename_arg(call, 0, &mut |call, path, fs| {
-            match fs.borrow_mut().do_open(&path[], 0, 0) {
-                Ok(fd) =>
-                    Some(Handle::new(fs.clone(), self.next_fd + 1, fd)),
+    fn do_writev(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let iovecs = match VFS::read_iovecs(call) {
+            Ok(v) => v,
+            Err(e) => return call.finish(errno_ret(e))
+        };
+        let h = match self.open_fds.get_mut(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h
+        };
+        let mut total = 0;
+        for &(base, len) in iovecs.iter() {
+            let buf = match call.read_bytes(base, len) {
+                Ok(b) => b,
+                Err(_) => return call.finish(errno_ret(libc::EFAULT))
+            };
+            match VFS::write_within_quota(&self.quota, &mut self.usage, h, &buf[]) {
+                Ok(n) => {
+                    total += n;
+                    if n < len {
+                        break;
+                    }
+                },
                 Err(err) => {
-                    call.finish(err.kind.to_errno());
-                    None
+                    if total == 0 {
+                        return call.finish(err.to_errno());
+                    }
+                    break;
                 }
             }
-        }) {
-            Some(fd) => {
-                let fd_num = (&fd as &io::Handle).get_virt_fd();
-                self.open_fds.insert(fd_num, fd);
-                call.finish(fd_num as u64);
+        }
+        call.finish(total as u64)
+    }
+
+    fn do_lseek(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let whence = match call.call.args[2] {
+            0 => SeekStyle::SeekSet,
+            1 => SeekStyle::SeekCur,
+            2 => SeekStyle::SeekEnd,
+            _ => return call.finish(errno_ret(libc::EINVAL))
+        };
+        let h = match self.open_fds.get_mut(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h
+        };
+        // Directory handles only have a cursor into their listing, which is
+        // all that seekdir() and rewinddir() need.
+        match h.stat() {
+            Ok(ref st) if st.kind == FileType::Directory => {
+                let offset = call.call.args[1] as i64;
+                let pos = match whence {
+                    SeekStyle::SeekSet => offset,
+                    SeekStyle::SeekCur => h._dir_offset as i64 + offset,
+                    SeekStyle::SeekEnd => return call.finish(errno_ret(libc::EINVAL))
+                };
+                if pos < 0 {
+                    return call.finish(errno_ret(libc::EINVAL));
+                }
+                h._dir_offset = pos as usize;
+                return call.finish(pos as u64);
             },
+            _ => {}
+        }
+        match (h as &mut io::Handle).seek(call.call.args[1] as i64, whence) {
+            Ok(pos) => call.finish(pos),
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    /// Lists a directory handle, including the "." and ".." entries that
+    /// filesystems leave out.
+    fn list_dir(&self, h: &Handle<'fs>) -> IoResult<Vec<DirEntry>> {
+        let st = try!(h.stat());
+        if st.kind != FileType::Directory {
+            return Err(errno::error(libc::ENOTDIR));
+        }
+        let parent = path::parent(h.get_path());
+        let parent_inode = match self.get_filesystem(&parent[], true) {
+            Ok((_, local, fs)) => match fs.borrow().do_stat(&local[]) {
+                Ok(pst) => pst.unstable.inode,
+                Err(_) => st.unstable.inode
+            },
+            Err(_) => st.unstable.inode
+        };
+
+        let mut entries = vec![
+            DirEntry {name: String::from_str("."), inode: st.unstable.inode, kind: FileType::Directory},
+            DirEntry {name: String::from_str(".."), inode: parent_inode, kind: FileType::Directory}
+        ];
+        entries.extend(try!(h.readdir()).into_iter());
+        Ok(entries)
+    }
+
+    fn do_getdents(&mut self, call: &mut events::Syscall, is_64: bool) -> SandboxResult<()> {
+        let fd = call.call.args[0] as i32;
+        let count = call.call.args[2] as usize;
+        // The listing is read once and kept on the handle until the cursor
+        // goes back to the start, so that rewinddir() sees changes
+        let fresh = match self.open_fds.get(&fd) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) if h._dir_offset > 0 && h._listing.is_some() => None,
+            Some(h) => match self.list_dir(h) {
+                Ok(e) => Some(e),
+                Err(err) => return call.finish(err.to_errno())
+            }
+        };
+        if fresh.is_some() {
+            self.open_fds.get_mut(&fd).unwrap()._listing = fresh;
+        }
+
+        let (buf, next, more) = {
+            let h = &self.open_fds[fd];
+            let entries = h._listing.as_ref().unwrap();
+            let mut buf = Vec::new();
+            let mut next = h._dir_offset;
+            for (i, entry) in entries.iter().enumerate().skip(h._dir_offset) {
+                let rec = if is_64 {
+                    linux_dirent64(entry, (i + 1) as u64)
+                } else {
+                    linux_dirent(entry, (i + 1) as u64)
+                };
+                if buf.len() + rec.len() > count {
+                    break;
+                }
+                buf.push_all(&rec[]);
+                next = i + 1;
+            }
+            (buf, next, next < entries.len())
+        };
+
+        if buf.is_empty() && more {
+            return call.finish(errno_ret(libc::EINVAL));
+        }
+        if call.write_bytes(call.call.args[1], &buf[]).is_err() {
+            return call.finish(errno_ret(libc::EFAULT));
+        }
+        match self.open_fds.get_mut(&fd) {
+            Some(h) => h._dir_offset = next,
             None => {}
         }
+        call.finish(buf.len() as u64)
+    }
+
+    fn do_fstat(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let res = match self.open_fds.get(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h.stat()
+        };
+        match res {
+            Ok(sbuf) => {
+                call.write_buf_arg(1, &Stat::from_file_stat(&sbuf));
+                call.finish(0)
+            },
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_ftruncate(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        if (call.call.args[1] as i64) < 0 {
+            return call.finish(errno_ret(libc::EINVAL));
+        }
+        let len = call.call.args[1];
+        let res = match self.open_fds.get(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h.stat().and_then(|st| {
+                if len > st.size {
+                    try!(self.quota.check_bytes(&self.usage, len - st.size));
+                }
+                try!(h.truncate(len));
+                Ok(st.size)
+            })
+        };
+        match res {
+            Ok(before) => {
+                self.usage.resize(before, len);
+                call.finish(0)
+            },
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_fsync(&mut self, call: &mut events::Syscall, data_only: bool) -> SandboxResult<()> {
+        let res = match self.open_fds.get(&(call.call.args[0] as i32)) {
+            None => return call.finish(errno_ret(libc::EBADF)),
+            Some(h) => h.sync(data_only)
+        };
+        match res {
+            Ok(_) => call.finish(0),
+            Err(err) => call.finish(err.to_errno())
+        }
+    }
+
+    fn do_close(&mut self, call: &mut events::Syscall) -> SandboxResult<()> {
+        let res = self.close(call.call.args[0] as i32);
+        finish_unit(call, res)
+    }
+
+    /// Closes a virtual fd. The fd is released even if its filesystem fails
+    /// to close the file.
+    pub fn close(&mut self, fd: i32) -> IoResult<()> {
+        let mut h = match self.open_fds.remove(&fd) {
+            None => return Err(errno::error(libc::EBADF)),
+            Some(h) => h
+        };
+        self.fds.release(fd);
+        self.proc_info.borrow_mut().fds.remove(&fd);
+        (&mut h as &mut io::Handle).close()
     }
 
     pub fn new() -> VFS<'fs> {
         let mut r = VFS {
-            filesystems: HashMap::new(),
-            cwd: String::new(),
-            next_fd: 0,
+            mounts: mount::MountTable::new(),
+            cwd: String::from_str("/"),
+            fds: FdAllocator::new(),
             open_fds: HashMap::new(),
-            whitelist: Vec::new()
+            whitelist: Vec::new(),
+            proc_info: Rc::new(RefCell::new(proc::ProcInfo::new())),
+            quota: Default::default(),
+            usage: Default::default(),
+            umask: 0o022
         };
 
         r.whitelist.push(String::from_str("/lib64/libc.so.6"));
@@ -234,22 +1299,93 @@ impl<'fs> VFS<'fs> {
         r.whitelist.push(String::from_str ("/etc/ld.so.cache"));
         r.whitelist.push(String::from_str ("/etc/ld.so.preload"));
 
-        r.whitelist.push(String::from_str ("/proc/self/exe"));
-
         r
     }
 }
 
+/// Reads a path argument. Paths that aren't UTF-8 can't name anything in
+/// the VFS.
+fn read_path_arg(call: &events::Syscall, arg_num: usize) -> IoResult<String> {
+    call.read_string_arg(arg_num).map_err(|_| errno::error(libc::ENOENT))
+}
+
+/// Finishes a syscall that returns 0 on success
+fn finish_unit(call: &mut events::Syscall, res: IoResult<()>) -> SandboxResult<()> {
+    match res {
+        Ok(_) => call.finish(0),
+        Err(err) => call.finish(err.to_errno())
+    }
+}
+
+fn timeval_ms(buf: &[u8]) -> u64 {
+    read_u64(&buf[0..8]) * 1000 + read_u64(&buf[8..16]) / 1000
+}
+
+fn push_le(buf: &mut Vec<u8>, v: u64, size: usize) {
+    for i in range(0, size) {
+        buf.push(((v >> (i * 8)) & 0xff) as u8);
+    }
+}
+
+fn dirent_type(kind: FileType) -> u8 {
+    match kind {
+        FileType::RegularFile => 8,
+        FileType::Directory => 4,
+        FileType::NamedPipe => 1,
+        FileType::BlockSpecial => 6,
+        FileType::Symlink => 10,
+        FileType::Unknown => 0
+    }
+}
+
+/// Serializes entry as a struct linux_dirent64, padded to 8 bytes
+pub fn linux_dirent64(entry: &DirEntry, offset: u64) -> Vec<u8> {
+    let reclen = (19 + entry.name.len() + 1 + 7) & !7;
+    let mut rec = Vec::with_capacity(reclen);
+    push_le(&mut rec, entry.inode, 8);
+    push_le(&mut rec, offset, 8);
+    push_le(&mut rec, reclen as u64, 2);
+    rec.push(dirent_type(entry.kind));
+    rec.push_all(entry.name.as_bytes());
+    let pad = reclen - rec.len();
+    rec.extend(repeat(0u8).take(pad));
+    rec
+}
+
+/// Serializes entry as the older struct linux_dirent, which keeps d_type in
+/// the last byte of the record
+pub fn linux_dirent(entry: &DirEntry, offset: u64) -> Vec<u8> {
+    let reclen = (18 + entry.name.len() + 2 + 7) & !7;
+    let mut rec = Vec::with_capacity(reclen);
+    push_le(&mut rec, entry.inode, 8);
+    push_le(&mut rec, offset, 8);
+    push_le(&mut rec, reclen as u64, 2);
+    rec.push_all(entry.name.as_bytes());
+    let pad = reclen - 1 - rec.len();
+    rec.extend(repeat(0u8).take(pad));
+    rec.push(dirent_type(entry.kind));
+    rec
+}
+
+fn read_u64(buf: &[u8]) -> u64 {
+    let mut v = 0u64;
+    for i in range(0, 8) {
+        v |= (buf[i] as u64) << (i * 8);
+    }
+    v
+}
+
+/// The x86_64 kernel's struct stat, as written back to the tracee
 #[repr(C)]
-struct Stat {
+pub struct Stat {
     st_dev: u64,
     st_ino: u64,
     st_nlink: u64,
 
-    st_mode: i64,
-    st_uid: i64,
-    st_gid: i64,
-    __pad0: i64,
+    st_mode: u32,
+    st_uid: u32,
+    st_gid: u32,
+    __pad0: i32,
     st_rdev: u64,
     st_size: i64,
     st_blksize: i64,
@@ -264,3 +1400,38 @@ struct Stat {
     __pad2: i64,
     __pad3: i64,
 }
+
+impl Stat {
+    pub fn from_file_stat(sbuf: &FileStat) -> Stat {
+        let file_type = match sbuf.kind {
+            FileType::RegularFile => 0o100000,
+            FileType::Directory => 0o040000,
+            FileType::NamedPipe => 0o010000,
+            FileType::BlockSpecial => 0o060000,
+            FileType::Symlink => 0o120000,
+            FileType::Unknown => 0
+        };
+        Stat {
+            st_dev: sbuf.unstable.device,
+            st_ino: sbuf.unstable.inode,
+            st_mode: file_type | sbuf.perm.bits(),
+            st_nlink: sbuf.unstable.nlink,
+            st_uid: sbuf.unstable.uid as u32,
+            st_gid: sbuf.unstable.gid as u32,
+            st_rdev: sbuf.unstable.rdev,
+            st_size: sbuf.size as i64,
+            st_blksize: sbuf.unstable.blksize as i64,
+            st_blocks: sbuf.unstable.blocks as i64,
+            st_atime: sbuf.accessed / 1000,
+            st_atime_nsec: (sbuf.accessed % 1000) * 1000000,
+            st_mtime: sbuf.modified / 1000,
+            st_mtime_nsec: (sbuf.modified % 1000) * 1000000,
+            st_ctime: sbuf.created / 1000,
+            st_ctime_nsec: (sbuf.created % 1000) * 1000000,
+            __pad0: 0,
+            __pad1: 0,
+            __pad2: 0,
+            __pad3: 0
+        }
+    }
+}
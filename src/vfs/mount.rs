@@ -0,0 +1,64 @@
+use vfs::FsRef;
+use vfs::path;
+
+struct Mount<'fs> {
+    point: String,
+    depth: usize,
+    fs: FsRef<'fs>
+}
+
+/// The set of filesystems mounted into a VFS.
+///
+/// Lookups pick the mount point with the most components in common with the
+/// path, so a filesystem mounted at /app shadows the one at / for anything
+/// beneath /app.
+pub struct MountTable<'fs> {
+    mounts: Vec<Mount<'fs>>
+}
+
+impl<'fs> MountTable<'fs> {
+    pub fn new() -> MountTable<'fs> {
+        MountTable {
+            mounts: Vec::new()
+        }
+    }
+
+    /// Mounts fs at mount_point, replacing anything already mounted there
+    pub fn mount(&mut self, mount_point: &str, fs: FsRef<'fs>) {
+        let point = path::normalize("/", mount_point);
+        self.mounts.retain(|m| m.point != point);
+        let depth = path::components(&point[]).len();
+        self.mounts.push(Mount {
+            point: point,
+            depth: depth,
+            fs: fs
+        });
+        self.mounts.sort_by(|a, b| b.depth.cmp(&a.depth));
+    }
+
+    pub fn unmount(&mut self, mount_point: &str) -> Option<FsRef<'fs>> {
+        let point = path::normalize("/", mount_point);
+        match self.mounts.iter().position(|m| m.point == point) {
+            Some(i) => Some(self.mounts.remove(i).fs),
+            None => None
+        }
+    }
+
+    /// Finds the filesystem that holds abs_path, which must already be
+    /// normalized.
+    ///
+    /// Returns the path relative to that filesystem's root, always starting
+    /// with "/", along with the filesystem itself. Since the lookup works on
+    /// whole components of a normalized path, the returned path can never
+    /// refer to anything above the mount's root.
+    pub fn resolve(&self, abs_path: &str) -> Option<(String, &FsRef<'fs>)> {
+        let parts = path::components(abs_path);
+        for m in self.mounts.iter() {
+            let point = path::components(&m.point[]);
+            if point.len() <= parts.len() && &point[] == &parts[..point.len()] {
+                return Some((path::join(&parts[point.len()..]), &m.fs));
+            }
+        }
+        None
+    }
+}
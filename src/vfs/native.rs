@@ -1,49 +1,128 @@
+#[allow(unstable)]
+extern crate libc;
+
 use vfs;
+use vfs::errno;
 use io;
-use std::io::{File, Read, Open, ReadWrite, IoResult, FileStat, IoError, IoErrorKind};
+use std::io::{File, Read, Write, ReadWrite, Open, Append, IoResult, FileStat, FileType, FilePermission, IoError, SeekStyle};
 use std::os::unix::prelude::AsRawFd;
 use std::io::fs;
 use std::collections::HashMap;
+use std::ffi::CString;
 
+/// Serves a directory on the host, with every path kept beneath root.
 pub struct NativeFS {
     root: Path,
-    fd_map: HashMap<i32, Box<File>>
+    fd_map: HashMap<i32, Box<File>>,
+    writable: bool
 }
 
 impl NativeFS {
     pub fn new(root: Path) -> Self {
         NativeFS {
             root: root,
-            fd_map: HashMap::new()
+            fd_map: HashMap::new(),
+            writable: true
+        }
+    }
+
+    /// Serves root without letting the sandbox create or change anything in
+    /// it. Attempts to fail with EROFS.
+    pub fn read_only(root: Path) -> Self {
+        NativeFS {
+            writable: false,
+            ..NativeFS::new(root)
+        }
+    }
+
+    /// Maps a filesystem-local path, which always starts with "/", onto the
+    /// host beneath root.
+    fn local_path(&self, path: &str) -> Path {
+        self.root.join(path.trim_left_matches('/'))
+    }
+
+    fn no_symlink(&self, path: &str) -> IoResult<Path> {
+        let local = self.local_path(path);
+        if try!(fs::lstat(&local)).kind == FileType::Symlink {
+            return Err(errno::error(libc::ELOOP));
+        }
+        Ok(local)
+    }
+
+    fn check_writable(&self) -> IoResult<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(errno::error(libc::EROFS))
         }
     }
 
     fn get_file(&mut self, handle: &io::Handle) -> Result<&mut File, IoError> {
         match self.fd_map.get_mut(&handle.get_local_fd()) {
             Some(f) => Ok(&mut **f),
-            None => Err(IoError {kind: IoErrorKind::FileNotFound, desc: "No Filesystem mounted", detail: None})
+            None => Err(errno::error(libc::EBADF))
         }
     }
 }
 
 impl vfs::Filesystem for NativeFS {
     #[allow(unstable)]
-    fn do_open(&mut self, path: &str, flags: i32, mode: i32) -> IoResult<i32> {
-        println!("Opening {:?}", self.root.join(path));
-        let f = File::open_mode(&self.root.join(path), Open, Read);
-        match f {
-            Ok(fd) => {
-                let num = fd.as_raw_fd();
-                self.fd_map.insert(num, Box::new(fd));
-                Ok(num)
+    fn do_open(&mut self, path: &str, flags: vfs::OpenFlags, mode: u32) -> IoResult<i32> {
+        let local = self.local_path(path);
+        let existing = fs::lstat(&local);
+        match existing {
+            Ok(ref st) => {
+                if flags.contains(vfs::Create | vfs::Exclusive) {
+                    return Err(errno::error(libc::EEXIST));
+                }
+                // The VFS resolves links before calling us, so anything left
+                // here would be followed on the host.
+                if st.kind == FileType::Symlink {
+                    return Err(errno::error(libc::ELOOP));
+                }
+                if flags.contains(vfs::Directory) && st.kind != FileType::Directory {
+                    return Err(errno::error(libc::ENOTDIR));
+                }
+                if st.kind == FileType::Directory && flags.is_writable() {
+                    return Err(errno::error(libc::EISDIR));
+                }
             },
-            Err(e) => Err(e)
+            Err(ref e) if !flags.contains(vfs::Create) => return Err(e.clone()),
+            // Only an open that would create the file changes the mount
+            Err(_) => try!(self.check_writable())
+        }
+        if flags.is_writable() || flags.contains(vfs::Truncate) {
+            try!(self.check_writable());
         }
+
+        // Old std only creates files it opens for writing, so a new file is
+        // made here whatever the access mode
+        if existing.is_err() {
+            try!(create_file(&local, mode));
+        }
+
+        let file_mode = if flags.contains(vfs::Append) {
+            Append
+        } else {
+            Open
+        };
+        let access = match (flags.is_readable(), flags.is_writable()) {
+            (true, false) => Read,
+            (false, true) => Write,
+            _ => ReadWrite
+        };
+
+        let mut f = try!(File::open_mode(&local, file_mode, access));
+        if flags.contains(vfs::Truncate) && flags.is_writable() {
+            try!(f.truncate(0));
+        }
+        let num = f.as_raw_fd();
+        self.fd_map.insert(num, Box::new(f));
+        Ok(num)
     }
 
     fn do_access(&self, path: &str) -> IoResult<()>{
-        println!("Accessing {:?}", self.root.join(path));
-        match fs::stat(&self.root.join(path)) {
+        match fs::stat(&self.local_path(path)) {
             Ok(stat) => {
                 Ok(())
             },
@@ -52,25 +131,190 @@ impl vfs::Filesystem for NativeFS {
     }
 
     fn do_stat(&self, path: &str) -> IoResult<FileStat> {
-        fs::stat(&self.root.join(path))
+        fs::stat(&self.local_path(path))
+    }
+
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat> {
+        fs::lstat(&self.local_path(path))
+    }
+
+    fn do_readlink(&self, path: &str) -> IoResult<String> {
+        let target = try!(fs::readlink(&self.local_path(path)));
+        match target.as_str() {
+            Some(s) => Ok(String::from_str(s)),
+            None => Err(errno::error(libc::EINVAL))
+        }
+    }
+
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat> {
+        match self.get_file(handle) {
+            Ok(f) => f.stat(),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<vfs::DirEntry>> {
+        let dir = match self.get_file(handle) {
+            Ok(f) => f.path().clone(),
+            Err(e) => return Err(e)
+        };
+        let mut entries = Vec::new();
+        for p in try!(fs::readdir(&dir)).iter() {
+            let st = try!(fs::lstat(p));
+            match p.filename_str() {
+                Some(name) => entries.push(vfs::DirEntry {
+                    name: String::from_str(name),
+                    inode: st.unstable.inode,
+                    kind: st.kind
+                }),
+                None => {}
+            }
+        }
+        Ok(entries)
+    }
+
+    fn do_ftruncate(&mut self, handle: &io::Handle, len: u64) -> IoResult<()> {
+        try!(self.check_writable());
+        let f = try!(self.get_file(handle));
+        f.truncate(len as i64)
+    }
+
+    fn do_fsync(&mut self, handle: &io::Handle, data_only: bool) -> IoResult<()> {
+        let f = try!(self.get_file(handle));
+        if data_only {
+            f.datasync()
+        } else {
+            f.fsync()
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        !self.writable
+    }
+
+    fn do_unlink(&mut self, path: &str) -> IoResult<()> {
+        try!(self.check_writable());
+        let local = self.local_path(path);
+        if try!(fs::lstat(&local)).kind == FileType::Directory {
+            return Err(errno::error(libc::EISDIR));
+        }
+        fs::unlink(&local)
+    }
+
+    fn do_rmdir(&mut self, path: &str) -> IoResult<()> {
+        try!(self.check_writable());
+        fs::rmdir(&self.local_path(path))
+    }
+
+    fn do_mkdir(&mut self, path: &str, mode: u32) -> IoResult<()> {
+        try!(self.check_writable());
+        fs::mkdir(&self.local_path(path), FilePermission::from_bits_truncate(mode))
+    }
+
+    fn do_rename(&mut self, from: &str, to: &str) -> IoResult<()> {
+        try!(self.check_writable());
+        fs::rename(&self.local_path(from), &self.local_path(to))
+    }
+
+    // Host symlinks are never created: the VFS checks a path before we act
+    // on it, and a task could swap one of its components for a link to
+    // anywhere on the host in between.
+    fn do_symlink(&mut self, _target: &str, _path: &str) -> IoResult<()> {
+        try!(self.check_writable());
+        Err(errno::error(libc::EPERM))
+    }
+
+    fn do_link(&mut self, from: &str, to: &str) -> IoResult<()> {
+        try!(self.check_writable());
+        fs::link(&self.local_path(from), &self.local_path(to))
+    }
+
+    // The rest act on the file a path names, and the host would follow a
+    // symlink there. The VFS has already resolved the path, so a link left
+    // at the end of it is refused just like in do_open.
+
+    fn do_chmod(&mut self, path: &str, mode: u32) -> IoResult<()> {
+        try!(self.check_writable());
+        let local = try!(self.no_symlink(path));
+        fs::chmod(&local, FilePermission::from_bits_truncate(mode))
+    }
+
+    fn do_truncate(&mut self, path: &str, len: u64) -> IoResult<()> {
+        try!(self.check_writable());
+        let local = try!(self.no_symlink(path));
+        let mut f = try!(File::open_mode(&local, Open, Write));
+        f.truncate(len as i64)
+    }
+
+    fn do_utimes(&mut self, path: &str, atime: u64, mtime: u64) -> IoResult<()> {
+        try!(self.check_writable());
+        let local = try!(self.no_symlink(path));
+        fs::change_file_times(&local, atime, mtime)
     }
 }
 
 impl io::Streaming for NativeFS {
     fn do_write(&mut self, handle: &io::Handle, buf: &[u8]) -> IoResult<usize> {
-        Ok(0)
+        try!(self.check_writable());
+        let f = try!(self.get_file(handle));
+        try!(f.write(buf));
+        Ok(buf.len())
     }
 
     fn do_read(&mut self, handle: &io::Handle, buf: &mut [u8]) -> IoResult<usize> {
-        println!("Reading from {:?}", handle.get_local_fd());
         match self.get_file(handle) {
             Ok(f) => f.read(buf),
             Err(e) => Err(e)
         }
     }
 
+    fn do_seek(&mut self, handle: &io::Handle, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        match self.get_file(handle) {
+            Ok(f) => {
+                try!(f.seek(offset, whence));
+                f.tell()
+            },
+            Err(e) => Err(e)
+        }
+    }
+
     fn do_close(&mut self, handle: &io::Handle) -> IoResult<()> {
-        Ok(())
+        // Dropping the File closes the host fd
+        match self.fd_map.remove(&handle.get_local_fd()) {
+            Some(_) => Ok(()),
+            None => Err(errno::error(libc::EBADF))
+        }
     }
 }
 
+/// Makes an empty file with exactly mode, which the VFS has already taken
+/// the sandbox's umask off. It starts out with no permissions at all, so
+/// nothing can open it before it has the right ones, and O_EXCL keeps it
+/// from following a link or taking over a file made in the meantime.
+fn create_file(local: &Path, mode: u32) -> IoResult<()> {
+    let path = CString::from_slice(local.as_vec());
+    unsafe {
+        let fd = ext::open(path.as_ptr(), libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | O_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(IoError::last_error());
+        }
+        let res = if ext::fchmod(fd, (mode & 0o7777) as libc::mode_t) < 0 {
+            Err(IoError::last_error())
+        } else {
+            Ok(())
+        };
+        ext::close(fd);
+        res
+    }
+}
+
+const O_CLOEXEC: libc::c_int = 0o2000000;
+
+mod ext {
+    use super::libc;
+    extern "C" {
+        pub fn open(path: *const libc::c_char, flags: libc::c_int, mode: libc::mode_t) -> libc::c_int;
+        pub fn fchmod(fd: libc::c_int, mode: libc::mode_t) -> libc::c_int;
+        pub fn close(fd: libc::c_int) -> libc::c_int;
+    }
+}
@@ -0,0 +1,404 @@
+#[allow(unstable)]
+extern crate libc;
+
+use vfs;
+use vfs::{FsRef, errno};
+use vfs::path;
+use io;
+use io::Handle;
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::io::{IoResult, FileStat, FileType, SeekStyle};
+
+struct OpenFile<'fs> {
+    path: String,
+    upper: Option<vfs::Handle<'fs>>,
+    lower: Option<vfs::Handle<'fs>>
+}
+
+impl<'fs> OpenFile<'fs> {
+    /// The handle that reads and writes go to, preferring the upper layer
+    fn active(&mut self) -> &mut vfs::Handle<'fs> {
+        match self.upper {
+            Some(ref mut h) => h,
+            None => self.lower.as_mut().unwrap()
+        }
+    }
+}
+
+/// Layers a writable filesystem over a read-only one.
+///
+/// Lookups see the upper layer first and fall through to the lower one.
+/// Changing a lower file copies it, and the directories above it, up first,
+/// and removed paths are hidden behind whiteouts rather than touching the
+/// lower layer.
+///
+/// Renaming a directory that exists in the lower layer fails with EXDEV,
+/// like the kernel's overlayfs, rather than copying up its whole tree.
+pub struct OverlayFS<'fs> {
+    upper: FsRef<'fs>,
+    lower: FsRef<'fs>,
+    whiteouts: HashSet<String>,
+    open_files: HashMap<i32, OpenFile<'fs>>,
+    next_fd: i32
+}
+
+impl<'fs> OverlayFS<'fs> {
+    pub fn new(upper: FsRef<'fs>, lower: FsRef<'fs>) -> OverlayFS<'fs> {
+        OverlayFS {
+            upper: upper,
+            lower: lower,
+            whiteouts: HashSet::new(),
+            open_files: HashMap::new(),
+            next_fd: 0
+        }
+    }
+
+    /// Hides path, and anything beneath it, in the lower layer
+    pub fn whiteout(&mut self, path: &str) {
+        self.whiteouts.insert(path::normalize("/", path));
+    }
+
+    fn is_whited_out(&self, path: &str) -> bool {
+        let parts = path::components(path);
+        range(1, parts.len() + 1).any(|n| self.whiteouts.contains(&path::join(&parts[..n])))
+    }
+
+    fn in_upper(&self, path: &str) -> bool {
+        self.upper.borrow().do_lstat(path).is_ok()
+    }
+
+    fn in_lower(&self, path: &str) -> bool {
+        !self.is_whited_out(path) && self.lower.borrow().do_lstat(path).is_ok()
+    }
+
+    /// The layer that path is visible in
+    fn layer(&self, path: &str) -> IoResult<&FsRef<'fs>> {
+        if self.in_upper(path) {
+            Ok(&self.upper)
+        } else if self.in_lower(path) {
+            Ok(&self.lower)
+        } else {
+            Err(errno::error(libc::ENOENT))
+        }
+    }
+
+    fn open_in(&self, layer: &FsRef<'fs>, fd: i32, path: &str, flags: vfs::OpenFlags, mode: u32) -> IoResult<vfs::Handle<'fs>> {
+        let local = try!(layer.borrow_mut().do_open(path, flags, mode));
+        Ok(vfs::Handle::new(layer.clone(), fd, local, String::from_str(path)))
+    }
+
+    /// Makes sure path exists in the upper layer, copying the lower layer's
+    /// file, directory or link there along with its permissions
+    fn copy_up(&self, path: &str) -> IoResult<()> {
+        if self.in_upper(path) {
+            return Ok(());
+        }
+        if !self.in_lower(path) {
+            return Err(errno::error(libc::ENOENT));
+        }
+        try!(self.copy_up_parents(path));
+
+        let st = try!(self.lower.borrow().do_lstat(path));
+        match st.kind {
+            FileType::RegularFile => {},
+            FileType::Directory => return self.upper.borrow_mut().do_mkdir(path, st.perm.bits()),
+            FileType::Symlink => {
+                let target = try!(self.lower.borrow().do_readlink(path));
+                return self.upper.borrow_mut().do_symlink(&target[], path);
+            },
+            _ => return Err(errno::error(libc::EPERM))
+        }
+
+        let mut src = try!(self.open_in(&self.lower, -1, path, vfs::ReadOnly, 0));
+        let mut dst = try!(self.open_in(&self.upper, -1, path, vfs::Create | vfs::WriteOnly | vfs::Truncate, st.perm.bits()));
+        let ret = OverlayFS::copy_data(&mut src, &mut dst);
+        let _ = src.close();
+        let _ = dst.close();
+        ret
+    }
+
+    /// Copies up every directory above path
+    fn copy_up_parents(&self, path: &str) -> IoResult<()> {
+        let parts = path::components(path);
+        for n in range(1, parts.len()) {
+            try!(self.copy_up(&path::join(&parts[..n])[]));
+        }
+        Ok(())
+    }
+
+    /// Hides the lower layer's copy of path, if it has one
+    fn whiteout_lower(&mut self, path: &str) {
+        if self.in_lower(path) {
+            self.whiteouts.insert(String::from_str(path));
+        }
+    }
+
+    /// Lifts the whiteout on path once something new has been created
+    /// there, while keeping whatever the lower layer has beneath it hidden
+    fn unhide(&mut self, path: &str) {
+        if !self.whiteouts.remove(path) {
+            return;
+        }
+        for entry in OverlayFS::list_layer(&self.lower, path).into_iter() {
+            self.whiteouts.insert(path::normalize(path, &entry.name[]));
+        }
+    }
+
+    fn list_layer(layer: &FsRef<'fs>, path: &str) -> Vec<vfs::DirEntry> {
+        let local = match layer.borrow_mut().do_open(path, vfs::ReadOnly | vfs::Directory, 0) {
+            Ok(fd) => fd,
+            Err(_) => return Vec::new()
+        };
+        let mut h = vfs::Handle::new(layer.clone(), -1, local, String::from_str(path));
+        let entries = h.readdir().unwrap_or(Vec::new());
+        let _ = h.close();
+        entries
+    }
+
+    fn is_empty_dir(&self, path: &str) -> bool {
+        if !OverlayFS::list_layer(&self.upper, path).is_empty() {
+            return false;
+        }
+        !self.in_lower(path) || OverlayFS::list_layer(&self.lower, path).iter().all(|entry| {
+            self.is_whited_out(&path::normalize(path, &entry.name[])[])
+        })
+    }
+
+    fn exists(&self, path: &str) -> IoResult<()> {
+        match self.layer(path) {
+            Ok(_) => Err(errno::error(libc::EEXIST)),
+            Err(_) => Ok(())
+        }
+    }
+
+    fn copy_data(src: &mut vfs::Handle<'fs>, dst: &mut vfs::Handle<'fs>) -> IoResult<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = try!(src.read(&mut buf));
+            if len == 0 {
+                return Ok(());
+            }
+            try!(dst.write(&buf[..len]));
+        }
+    }
+
+    fn get_open(&mut self, handle: &io::Handle) -> IoResult<&mut OpenFile<'fs>> {
+        match self.open_files.get_mut(&handle.get_local_fd()) {
+            Some(f) => Ok(f),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
+
+impl<'fs> vfs::Filesystem for OverlayFS<'fs> {
+    fn do_open(&mut self, path: &str, flags: vfs::OpenFlags, mode: u32) -> IoResult<i32> {
+        let fd = self.next_fd;
+        let writing = flags.is_writable() || flags.contains(vfs::Create);
+        let file = if writing {
+            if !self.in_upper(path) && self.in_lower(path) {
+                if flags.contains(vfs::Create | vfs::Exclusive) {
+                    return Err(errno::error(libc::EEXIST));
+                }
+                try!(self.copy_up(path));
+            } else {
+                try!(self.copy_up_parents(path));
+            }
+            let upper = try!(self.open_in(&self.upper, fd, path, flags, mode));
+            self.unhide(path);
+            OpenFile {path: String::from_str(path), upper: Some(upper), lower: None}
+        } else {
+            let st = try!(try!(self.layer(path)).borrow().do_lstat(path));
+            if st.kind == FileType::Directory {
+                // Listing a directory merges both layers, so keep a handle
+                // on each one it exists in
+                let upper = if self.in_upper(path) {
+                    Some(try!(self.open_in(&self.upper, fd, path, flags, mode)))
+                } else {
+                    None
+                };
+                let lower = match self.lower.borrow().do_lstat(path) {
+                    Ok(ref st) if st.kind == FileType::Directory && !self.is_whited_out(path) =>
+                        Some(try!(self.open_in(&self.lower, fd, path, flags, mode))),
+                    _ => None
+                };
+                OpenFile {path: String::from_str(path), upper: upper, lower: lower}
+            } else if self.in_upper(path) {
+                OpenFile {path: String::from_str(path), upper: Some(try!(self.open_in(&self.upper, fd, path, flags, mode))), lower: None}
+            } else {
+                OpenFile {path: String::from_str(path), upper: None, lower: Some(try!(self.open_in(&self.lower, fd, path, flags, mode)))}
+            }
+        };
+        self.next_fd += 1;
+        self.open_files.insert(fd, file);
+        Ok(fd)
+    }
+
+    fn do_access(&self, path: &str) -> IoResult<()> {
+        try!(self.layer(path)).borrow().do_access(path)
+    }
+
+    fn do_stat(&self, path: &str) -> IoResult<FileStat> {
+        try!(self.layer(path)).borrow().do_stat(path)
+    }
+
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat> {
+        try!(self.layer(path)).borrow().do_lstat(path)
+    }
+
+    fn do_readlink(&self, path: &str) -> IoResult<String> {
+        try!(self.layer(path)).borrow().do_readlink(path)
+    }
+
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat> {
+        try!(self.get_open(handle)).active().stat()
+    }
+
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<vfs::DirEntry>> {
+        let mut entries = BTreeMap::new();
+        let dir = {
+            let file = try!(self.get_open(handle));
+            // Upper entries replace lower ones with the same name
+            for layer in vec![&file.lower, &file.upper].into_iter() {
+                match *layer {
+                    Some(ref h) => {
+                        for entry in try!(h.readdir()).into_iter() {
+                            entries.insert(entry.name.clone(), entry);
+                        }
+                    },
+                    None => {}
+                }
+            }
+            file.path.clone()
+        };
+        Ok(entries.into_iter().map(|(_, entry)| entry).filter(|entry| {
+            let child = path::normalize(&dir[], &entry.name[]);
+            !self.is_whited_out(&child[]) || self.in_upper(&child[])
+        }).collect())
+    }
+
+    fn do_ftruncate(&mut self, handle: &io::Handle, len: u64) -> IoResult<()> {
+        try!(self.get_open(handle)).active().truncate(len)
+    }
+
+    fn do_fsync(&mut self, handle: &io::Handle, data_only: bool) -> IoResult<()> {
+        try!(self.get_open(handle)).active().sync(data_only)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.upper.borrow().is_read_only()
+    }
+
+    fn do_unlink(&mut self, path: &str) -> IoResult<()> {
+        if try!(self.do_lstat(path)).kind == FileType::Directory {
+            return Err(errno::error(libc::EISDIR));
+        }
+        if self.in_upper(path) {
+            try!(self.upper.borrow_mut().do_unlink(path));
+        }
+        self.whiteout_lower(path);
+        Ok(())
+    }
+
+    fn do_rmdir(&mut self, path: &str) -> IoResult<()> {
+        if try!(self.do_lstat(path)).kind != FileType::Directory {
+            return Err(errno::error(libc::ENOTDIR));
+        }
+        if !self.is_empty_dir(path) {
+            return Err(errno::error(libc::ENOTEMPTY));
+        }
+        if self.in_upper(path) {
+            try!(self.upper.borrow_mut().do_rmdir(path));
+        }
+        self.whiteout_lower(path);
+        Ok(())
+    }
+
+    fn do_mkdir(&mut self, path: &str, mode: u32) -> IoResult<()> {
+        try!(self.exists(path));
+        try!(self.copy_up_parents(path));
+        try!(self.upper.borrow_mut().do_mkdir(path, mode));
+        self.unhide(path);
+        Ok(())
+    }
+
+    fn do_rename(&mut self, from: &str, to: &str) -> IoResult<()> {
+        let from_dir = try!(self.do_lstat(from)).kind == FileType::Directory;
+        if from_dir && self.in_lower(from) {
+            return Err(errno::error(libc::EXDEV));
+        }
+        // The upper layer can't see what it would be replacing in the
+        // lower one, so check that here
+        match self.do_lstat(to) {
+            Ok(st) => match (from_dir, st.kind == FileType::Directory) {
+                (true, false) => return Err(errno::error(libc::ENOTDIR)),
+                (false, true) => return Err(errno::error(libc::EISDIR)),
+                (true, true) if !self.is_empty_dir(to) => return Err(errno::error(libc::ENOTEMPTY)),
+                _ => {}
+            },
+            Err(_) => {}
+        }
+        try!(self.copy_up(from));
+        try!(self.copy_up_parents(to));
+        try!(self.upper.borrow_mut().do_rename(from, to));
+        self.whiteout_lower(from);
+        self.unhide(to);
+        Ok(())
+    }
+
+    fn do_symlink(&mut self, target: &str, path: &str) -> IoResult<()> {
+        try!(self.exists(path));
+        try!(self.copy_up_parents(path));
+        try!(self.upper.borrow_mut().do_symlink(target, path));
+        self.unhide(path);
+        Ok(())
+    }
+
+    fn do_link(&mut self, from: &str, to: &str) -> IoResult<()> {
+        try!(self.exists(to));
+        try!(self.copy_up(from));
+        try!(self.copy_up_parents(to));
+        try!(self.upper.borrow_mut().do_link(from, to));
+        self.unhide(to);
+        Ok(())
+    }
+
+    fn do_chmod(&mut self, path: &str, mode: u32) -> IoResult<()> {
+        try!(self.copy_up(path));
+        self.upper.borrow_mut().do_chmod(path, mode)
+    }
+
+    fn do_truncate(&mut self, path: &str, len: u64) -> IoResult<()> {
+        try!(self.copy_up(path));
+        self.upper.borrow_mut().do_truncate(path, len)
+    }
+
+    fn do_utimes(&mut self, path: &str, atime: u64, mtime: u64) -> IoResult<()> {
+        try!(self.copy_up(path));
+        self.upper.borrow_mut().do_utimes(path, atime, mtime)
+    }
+}
+
+impl<'fs> io::Streaming for OverlayFS<'fs> {
+    fn do_write(&mut self, handle: &io::Handle, buf: &[u8]) -> IoResult<usize> {
+        try!(self.get_open(handle)).active().write(buf)
+    }
+
+    fn do_read(&mut self, handle: &io::Handle, buf: &mut [u8]) -> IoResult<usize> {
+        try!(self.get_open(handle)).active().read(buf)
+    }
+
+    fn do_seek(&mut self, handle: &io::Handle, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        try!(self.get_open(handle)).active().seek(offset, whence)
+    }
+
+    fn do_close(&mut self, handle: &io::Handle) -> IoResult<()> {
+        let file = match self.open_files.remove(&handle.get_local_fd()) {
+            Some(f) => f,
+            None => return Err(errno::error(libc::EBADF))
+        };
+        for mut h in file.upper.into_iter().chain(file.lower.into_iter()) {
+            try!(h.close());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+/// Lexically normalizes path against cwd, returning an absolute path with no
+/// empty, "." or ".." components.
+///
+/// ".." at the root stays at the root, the same way the kernel treats "/..".
+pub fn normalize(cwd: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    let full = if path.starts_with("/") {
+        vec![path]
+    } else {
+        vec![cwd, path]
+    };
+
+    for p in full.iter() {
+        for component in p.split('/') {
+            match component {
+                "" | "." => {},
+                ".." => {parts.pop();},
+                c => parts.push(c)
+            }
+        }
+    }
+
+    join(&parts[])
+}
+
+/// Splits an absolute, normalized path into its components
+pub fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|c| !c.is_empty()).collect()
+}
+
+/// Joins components back into an absolute path
+pub fn join(parts: &[&str]) -> String {
+    let mut ret = String::new();
+    for c in parts.iter() {
+        ret.push('/');
+        ret.push_str(*c);
+    }
+    if ret.is_empty() {
+        ret.push('/');
+    }
+    ret
+}
+
+/// Returns the directory containing path, which must be absolute and
+/// normalized.
+pub fn parent(path: &str) -> String {
+    let parts = components(path);
+    if parts.is_empty() {
+        String::from_str("/")
+    } else {
+        join(&parts[..parts.len() - 1])
+    }
+}
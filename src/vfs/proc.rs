@@ -0,0 +1,253 @@
+#[allow(unstable)]
+extern crate libc;
+
+use vfs;
+use vfs::errno;
+use io;
+use std::collections::{HashMap, BTreeMap};
+use std::io::{IoResult, IoError, FileStat, FileType, SeekStyle, File};
+use std::cmp;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// The parts of the sandbox's state that ProcFS reports, kept up to date by
+/// the VFS that mounts it.
+pub struct ProcInfo {
+    pub pid: i32,
+    pub exe: String,
+    pub cwd: String,
+    pub fds: BTreeMap<i32, String>
+}
+
+impl ProcInfo {
+    pub fn new() -> ProcInfo {
+        ProcInfo {
+            pid: 0,
+            exe: String::from_str("/"),
+            cwd: String::from_str("/"),
+            fds: BTreeMap::new()
+        }
+    }
+}
+
+pub type ProcInfoRef = Rc<RefCell<ProcInfo>>;
+
+enum Node {
+    Dir(Vec<String>),
+    Link(String),
+    File(Vec<u8>)
+}
+
+struct OpenNode {
+    path: String,
+    data: Vec<u8>,
+    pos: usize
+}
+
+/// A synthetic /proc that answers queries about the sandboxed process with
+/// paths from inside the VFS instead of the host's.
+///
+/// Only /proc/self is provided, with exe, cwd, fd/ and maps.
+pub struct ProcFS {
+    info: ProcInfoRef,
+    open_nodes: HashMap<i32, OpenNode>,
+    next_fd: i32
+}
+
+impl ProcFS {
+    pub fn new(info: ProcInfoRef) -> ProcFS {
+        ProcFS {
+            info: info,
+            open_nodes: HashMap::new(),
+            next_fd: 0
+        }
+    }
+
+    fn lookup(&self, path: &str) -> IoResult<Node> {
+        let info = self.info.borrow();
+        match path {
+            "/" => Ok(Node::Dir(vec![String::from_str("self")])),
+            "/self" => Ok(Node::Dir(vec![
+                String::from_str("cwd"),
+                String::from_str("exe"),
+                String::from_str("fd"),
+                String::from_str("maps")
+            ])),
+            "/self/cwd" => Ok(Node::Link(info.cwd.clone())),
+            "/self/exe" => Ok(Node::Link(info.exe.clone())),
+            "/self/maps" => Ok(Node::File(ProcFS::virtual_maps(info.pid))),
+            "/self/fd" => Ok(Node::Dir(info.fds.keys().map(|fd| fd.to_string()).collect())),
+            p if p.starts_with("/self/fd/") => {
+                let name = &p["/self/fd/".len()..];
+                match info.fds.iter().find(|&(fd, _)| fd.to_string() == name) {
+                    Some((_, target)) => Ok(Node::Link(target.clone())),
+                    None => Err(not_found())
+                }
+            },
+            _ => Err(not_found())
+        }
+    }
+
+    /// Reads the host's maps for pid, keeping the address ranges but
+    /// dropping device numbers, inodes and host file names.
+    fn virtual_maps(pid: i32) -> Vec<u8> {
+        let host = match File::open(&Path::new(format!("/proc/{}/maps", pid))).and_then(|mut f| f.read_to_string()) {
+            Ok(s) => s,
+            Err(_) => return Vec::new()
+        };
+        let mut out = String::new();
+        for line in host.lines() {
+            let fields: Vec<&str> = line.split(' ').filter(|f| !f.is_empty()).collect();
+            if fields.len() < 5 {
+                continue;
+            }
+            let name = match fields.get(5) {
+                Some(n) if n.starts_with("[") => *n,
+                _ => ""
+            };
+            out.push_str(&format!("{} {} {} 00:00 0 {}\n", fields[0], fields[1], fields[2], name)[]);
+        }
+        out.into_bytes()
+    }
+
+    fn inode_for(path: &str) -> u64 {
+        // Stable, small and unique enough for the handful of nodes we serve
+        let mut h = 1u64;
+        for b in path.bytes() {
+            h = h.wrapping_mul(31).wrapping_add(b as u64);
+        }
+        h
+    }
+
+    fn stat_node(path: &str, node: &Node) -> FileStat {
+        match *node {
+            Node::Dir(_) => vfs::synthetic_stat(FileType::Directory, 0o555, 0, ProcFS::inode_for(path)),
+            Node::Link(ref target) => vfs::synthetic_stat(FileType::Symlink, 0o777, target.len() as u64, ProcFS::inode_for(path)),
+            Node::File(_) => vfs::synthetic_stat(FileType::RegularFile, 0o444, 0, ProcFS::inode_for(path))
+        }
+    }
+
+    fn get_open(&mut self, handle: &io::Handle) -> IoResult<&mut OpenNode> {
+        match self.open_nodes.get_mut(&handle.get_local_fd()) {
+            Some(n) => Ok(n),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
+
+fn not_found() -> IoError {
+    errno::error(libc::ENOENT)
+}
+
+fn read_only() -> IoError {
+    errno::error(libc::EROFS)
+}
+
+impl vfs::Filesystem for ProcFS {
+    fn do_open(&mut self, path: &str, flags: vfs::OpenFlags, _mode: u32) -> IoResult<i32> {
+        if flags.is_writable() || flags.contains(vfs::Create) {
+            return Err(read_only());
+        }
+        let data = match try!(self.lookup(path)) {
+            Node::File(data) => data,
+            Node::Dir(_) => Vec::new(),
+            Node::Link(_) => return Err(errno::error(libc::ELOOP))
+        };
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.open_nodes.insert(fd, OpenNode {
+            path: String::from_str(path),
+            data: data,
+            pos: 0
+        });
+        Ok(fd)
+    }
+
+    fn do_access(&self, path: &str) -> IoResult<()> {
+        self.lookup(path).map(|_| ())
+    }
+
+    fn do_stat(&self, path: &str) -> IoResult<FileStat> {
+        self.do_lstat(path)
+    }
+
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat> {
+        let node = try!(self.lookup(path));
+        Ok(ProcFS::stat_node(path, &node))
+    }
+
+    fn do_readlink(&self, path: &str) -> IoResult<String> {
+        match try!(self.lookup(path)) {
+            Node::Link(target) => Ok(target),
+            _ => Err(errno::error(libc::EINVAL))
+        }
+    }
+
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat> {
+        let path = try!(self.get_open(handle)).path.clone();
+        self.do_lstat(&path[])
+    }
+
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<vfs::DirEntry>> {
+        let path = try!(self.get_open(handle)).path.clone();
+        match try!(self.lookup(&path[])) {
+            Node::Dir(names) => {
+                let mut entries = Vec::new();
+                for name in names.into_iter() {
+                    let child = vfs::path::normalize(&path[], &name[]);
+                    let node = try!(self.lookup(&child[]));
+                    entries.push(vfs::DirEntry {
+                        inode: ProcFS::inode_for(&child[]),
+                        kind: ProcFS::stat_node(&child[], &node).kind,
+                        name: name
+                    });
+                }
+                Ok(entries)
+            },
+            _ => Err(errno::error(libc::ENOTDIR))
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+impl io::Streaming for ProcFS {
+    fn do_write(&mut self, _handle: &io::Handle, _buf: &[u8]) -> IoResult<usize> {
+        Err(read_only())
+    }
+
+    fn do_read(&mut self, handle: &io::Handle, buf: &mut [u8]) -> IoResult<usize> {
+        let node = try!(self.get_open(handle));
+        let start = cmp::min(node.pos, node.data.len());
+        let len = cmp::min(buf.len(), node.data.len() - start);
+        for i in range(0, len) {
+            buf[i] = node.data[start + i];
+        }
+        node.pos = start + len;
+        Ok(len)
+    }
+
+    fn do_seek(&mut self, handle: &io::Handle, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        let node = try!(self.get_open(handle));
+        let base = match whence {
+            SeekStyle::SeekSet => 0,
+            SeekStyle::SeekCur => node.pos as i64,
+            SeekStyle::SeekEnd => node.data.len() as i64
+        };
+        node.pos = match base.checked_add(offset) {
+            Some(p) if p < 0 => return Err(errno::error(libc::EINVAL)),
+            Some(p) => p as usize,
+            None => return Err(errno::error(libc::EOVERFLOW))
+        };
+        Ok(node.pos as u64)
+    }
+
+    fn do_close(&mut self, handle: &io::Handle) -> IoResult<()> {
+        match self.open_nodes.remove(&handle.get_local_fd()) {
+            Some(_) => Ok(()),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
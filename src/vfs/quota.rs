@@ -0,0 +1,88 @@
+#[allow(unstable)]
+extern crate libc;
+
+use vfs::errno;
+use std::io::{IoResult, FileStat, FileType};
+
+/// Limits on what a sandbox may store through its VFS. None means no limit.
+///
+/// Usage only counts what the sandbox changes once it is running: files that
+/// were already in a mounted filesystem are free, and deleting one of them
+/// never brings usage below zero.
+#[derive(Show, Clone, Copy, PartialEq, Default)]
+pub struct Quota {
+    /// Bytes written beyond the original size of files; going over fails
+    /// with EDQUOT
+    pub max_bytes: Option<u64>,
+    /// Files, directories and links created; going over fails with ENOSPC
+    pub max_inodes: Option<u64>,
+    /// Files open through the VFS at once; going over fails with EMFILE
+    pub max_open_files: Option<usize>
+}
+
+/// How much of its Quota a sandbox is using
+#[derive(Show, Clone, Copy, PartialEq, Default)]
+pub struct Usage {
+    pub bytes: u64,
+    pub inodes: u64,
+    pub open_files: usize
+}
+
+impl Quota {
+    pub fn check_bytes(&self, usage: &Usage, growth: u64) -> IoResult<()> {
+        match self.max_bytes {
+            Some(max) if usage.bytes + growth > max => Err(errno::error(libc::EDQUOT)),
+            _ => Ok(())
+        }
+    }
+
+    pub fn check_inodes(&self, usage: &Usage) -> IoResult<()> {
+        match self.max_inodes {
+            Some(max) if usage.inodes >= max => Err(errno::error(libc::ENOSPC)),
+            _ => Ok(())
+        }
+    }
+
+    pub fn check_open_files(&self, open_files: usize) -> IoResult<()> {
+        match self.max_open_files {
+            Some(max) if open_files >= max => Err(errno::error(libc::EMFILE)),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Usage {
+    /// Records a file changing size
+    pub fn resize(&mut self, before: u64, after: u64) {
+        if after > before {
+            self.bytes += after - before;
+        } else {
+            self.free_bytes(before - after);
+        }
+    }
+
+    pub fn free_bytes(&mut self, bytes: u64) {
+        self.bytes = if self.bytes > bytes { self.bytes - bytes } else { 0 };
+    }
+
+    /// Records the file that st described losing one of its names, which
+    /// frees it if that was the last one
+    pub fn unlinked(&mut self, st: &FileStat) {
+        if st.kind == FileType::Directory || st.unstable.nlink <= 1 {
+            if st.kind == FileType::RegularFile {
+                self.free_bytes(st.size);
+            }
+            self.remove_inode();
+        }
+    }
+
+    pub fn add_inode(&mut self) {
+        self.inodes += 1;
+    }
+
+    pub fn remove_inode(&mut self) {
+        if self.inodes > 0 {
+            self.inodes -= 1;
+        }
+    }
+}
@@ -0,0 +1,407 @@
+#[allow(unstable)]
+extern crate libc;
+
+use vfs;
+use vfs::errno;
+use vfs::path;
+use io;
+use std::collections::{HashMap, BTreeSet};
+use std::io::{IoResult, IoError, FileStat, FileType, SeekStyle, File};
+use std::cmp;
+
+const BLOCK: usize = 512;
+
+enum Kind {
+    Dir(BTreeSet<String>),
+    File {offset: usize, size: usize},
+    Link(String)
+}
+
+struct Entry {
+    kind: Kind,
+    perm: u32,
+    mtime: u64,
+    inode: u64
+}
+
+impl Entry {
+    fn file_type(&self) -> FileType {
+        match self.kind {
+            Kind::Dir(_) => FileType::Directory,
+            Kind::File{..} => FileType::RegularFile,
+            Kind::Link(_) => FileType::Symlink
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self.kind {
+            Kind::Dir(_) => 0,
+            Kind::File{size, ..} => size as u64,
+            Kind::Link(ref target) => target.len() as u64
+        }
+    }
+}
+
+struct OpenEntry {
+    path: String,
+    pos: u64
+}
+
+/// Serves the contents of a tar archive, read-only, without unpacking it.
+///
+/// The archive is held in memory and indexed once when the filesystem is
+/// created. ustar archives and GNU long names are understood; directories
+/// that only appear as part of other entries' paths are filled in, and
+/// anything besides files, directories and links is left out.
+pub struct TarFS {
+    data: Vec<u8>,
+    entries: HashMap<String, Entry>,
+    next_ino: u64,
+    open_entries: HashMap<i32, OpenEntry>,
+    next_fd: i32
+}
+
+impl TarFS {
+    pub fn new(data: Vec<u8>) -> IoResult<TarFS> {
+        let mut fs = TarFS {
+            data: Vec::new(),
+            entries: HashMap::new(),
+            next_ino: 1,
+            open_entries: HashMap::new(),
+            next_fd: 0
+        };
+        fs.insert(String::from_str("/"), Kind::Dir(BTreeSet::new()), 0o755, 0);
+        try!(fs.index(&data[]));
+        fs.data = data;
+        Ok(fs)
+    }
+
+    pub fn open(archive: &Path) -> IoResult<TarFS> {
+        let data = try!(File::open(archive).and_then(|mut f| f.read_to_end()));
+        TarFS::new(data)
+    }
+
+    fn index(&mut self, data: &[u8]) -> IoResult<()> {
+        let mut offset = 0;
+        let mut long_name: Option<String> = None;
+        let mut long_link: Option<String> = None;
+        let mut hard_links = Vec::new();
+
+        while offset + BLOCK <= data.len() {
+            let header = &data[offset..offset + BLOCK];
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            if try!(parse_number(&header[148..156])) != checksum(header) {
+                return Err(bad_archive());
+            }
+            let size = try!(parse_number(&header[124..136])) as usize;
+            let start = offset + BLOCK;
+            match start.checked_add(size) {
+                Some(end) if end <= data.len() => {},
+                _ => return Err(bad_archive())
+            }
+            offset = start + (size + BLOCK - 1) / BLOCK * BLOCK;
+
+            let typeflag = header[156];
+            match typeflag {
+                b'L' => {
+                    long_name = Some(field_str(&data[start..start + size]));
+                    continue;
+                },
+                b'K' => {
+                    long_link = Some(field_str(&data[start..start + size]));
+                    continue;
+                },
+                _ => {}
+            }
+
+            let name = match long_name.take() {
+                Some(name) => name,
+                None => header_name(header)
+            };
+            let link = match long_link.take() {
+                Some(link) => link,
+                None => field_str(&header[157..257])
+            };
+            let path = path::normalize("/", &name[]);
+            let perm = (try!(parse_number(&header[100..108])) & 0o7777) as u32;
+            let mtime = try!(parse_number(&header[136..148])) * 1000;
+
+            // Only a directory can stand in for the root
+            if path == "/" {
+                match typeflag {
+                    0 | b'0' | b'7' | b'2' | b'1' => return Err(bad_archive()),
+                    _ => {}
+                }
+            }
+
+            match typeflag {
+                0 | b'0' | b'7' => self.insert(path, Kind::File {offset: start, size: size}, perm, mtime),
+                b'5' => self.insert(path, Kind::Dir(BTreeSet::new()), perm, mtime),
+                b'2' => self.insert(path, Kind::Link(link), perm, mtime),
+                b'1' => hard_links.push((path, path::normalize("/", &link[]), perm, mtime)),
+                // pax headers, devices and fifos
+                _ => {}
+            }
+        }
+
+        // Hard links can only point back at files seen earlier in the
+        // archive, so share their data once everything is indexed.
+        for (path, target, perm, mtime) in hard_links.into_iter() {
+            let kind = match self.entries.get(&target) {
+                Some(&Entry {kind: Kind::File {offset, size}, ..}) => Kind::File {offset: offset, size: size},
+                _ => continue
+            };
+            self.insert(path, kind, perm, mtime);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, path: String, kind: Kind, perm: u32, mtime: u64) {
+        if path != "/" {
+            let parent = path::parent(&path[]);
+            self.add_dir(&parent[]);
+            let name = String::from_str(*path::components(&path[]).last().unwrap());
+            match self.entries.get_mut(&parent) {
+                Some(&mut Entry {kind: Kind::Dir(ref mut names), ..}) => {
+                    names.insert(name);
+                },
+                _ => {}
+            }
+        }
+
+        let is_dir = match kind {
+            Kind::Dir(_) => true,
+            _ => false
+        };
+        let inode = match self.entries.get_mut(&path) {
+            Some(entry) => {
+                // Directories can be listed after their contents; keep the
+                // children already recorded
+                match (is_dir, &entry.kind) {
+                    (true, &Kind::Dir(_)) => {
+                        entry.perm = perm;
+                        entry.mtime = mtime;
+                        return;
+                    },
+                    _ => entry.inode
+                }
+            },
+            None => {
+                let inode = self.next_ino;
+                self.next_ino += 1;
+                inode
+            }
+        };
+        self.entries.insert(path, Entry {
+            kind: kind,
+            perm: perm,
+            mtime: mtime,
+            inode: inode
+        });
+    }
+
+    fn add_dir(&mut self, path: &str) {
+        if !self.entries.contains_key(path) {
+            self.insert(String::from_str(path), Kind::Dir(BTreeSet::new()), 0o755, 0);
+        }
+    }
+
+    fn lookup(&self, path: &str) -> IoResult<&Entry> {
+        match self.entries.get(path) {
+            Some(entry) => Ok(entry),
+            None => Err(errno::error(libc::ENOENT))
+        }
+    }
+
+    fn stat_entry(entry: &Entry) -> FileStat {
+        let mut st = vfs::synthetic_stat(entry.file_type(), entry.perm, entry.size(), entry.inode);
+        st.created = entry.mtime;
+        st.modified = entry.mtime;
+        st.accessed = entry.mtime;
+        st
+    }
+
+    fn get_open(&mut self, handle: &io::Handle) -> IoResult<&mut OpenEntry> {
+        match self.open_entries.get_mut(&handle.get_local_fd()) {
+            Some(e) => Ok(e),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
+
+fn bad_archive() -> IoError {
+    errno::error(libc::EIO)
+}
+
+fn read_only() -> IoError {
+    errno::error(libc::EROFS)
+}
+
+/// The NUL-terminated string at the start of a header field
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn header_name(header: &[u8]) -> String {
+    let name = field_str(&header[0..100]);
+    if &header[257..262] != b"ustar" {
+        return name;
+    }
+    let prefix = field_str(&header[345..500]);
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Parses a numeric header field, either as NUL or space padded octal or,
+/// for values too big for that, GNU's base-256 with the top bit set.
+fn parse_number(field: &[u8]) -> IoResult<u64> {
+    if field.len() > 0 && field[0] & 0x80 != 0 {
+        let mut n = (field[0] & 0x7f) as u64;
+        for b in field[1..].iter() {
+            if n >> 56 != 0 {
+                return Err(bad_archive());
+            }
+            n = (n << 8) | *b as u64;
+        }
+        return Ok(n);
+    }
+    let mut n = 0u64;
+    for b in field.iter() {
+        match *b {
+            b'0'...b'7' => n = n * 8 + (*b - b'0') as u64,
+            0 | b' ' => {},
+            _ => return Err(bad_archive())
+        }
+    }
+    Ok(n)
+}
+
+/// The header's checksum, counting the checksum field itself as spaces
+fn checksum(header: &[u8]) -> u64 {
+    header.iter().enumerate().fold(0u64, |sum, (i, b)| {
+        sum + if i >= 148 && i < 156 { b' ' as u64 } else { *b as u64 }
+    })
+}
+
+impl vfs::Filesystem for TarFS {
+    fn do_open(&mut self, path: &str, flags: vfs::OpenFlags, _mode: u32) -> IoResult<i32> {
+        if flags.is_writable() || flags.contains(vfs::Create) {
+            return Err(read_only());
+        }
+        match try!(self.lookup(path)).kind {
+            Kind::Link(_) => return Err(errno::error(libc::ELOOP)),
+            Kind::File{..} if flags.contains(vfs::Directory) => return Err(errno::error(libc::ENOTDIR)),
+            _ => {}
+        }
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.open_entries.insert(fd, OpenEntry {
+            path: String::from_str(path),
+            pos: 0
+        });
+        Ok(fd)
+    }
+
+    fn do_access(&self, path: &str) -> IoResult<()> {
+        self.lookup(path).map(|_| ())
+    }
+
+    fn do_stat(&self, path: &str) -> IoResult<FileStat> {
+        self.do_lstat(path)
+    }
+
+    fn do_lstat(&self, path: &str) -> IoResult<FileStat> {
+        Ok(TarFS::stat_entry(try!(self.lookup(path))))
+    }
+
+    fn do_readlink(&self, path: &str) -> IoResult<String> {
+        match try!(self.lookup(path)).kind {
+            Kind::Link(ref target) => Ok(target.clone()),
+            _ => Err(errno::error(libc::EINVAL))
+        }
+    }
+
+    fn do_fstat(&mut self, handle: &io::Handle) -> IoResult<FileStat> {
+        let path = try!(self.get_open(handle)).path.clone();
+        self.do_lstat(&path[])
+    }
+
+    fn do_readdir(&mut self, handle: &io::Handle) -> IoResult<Vec<vfs::DirEntry>> {
+        let path = try!(self.get_open(handle)).path.clone();
+        match try!(self.lookup(&path[])).kind {
+            Kind::Dir(ref names) => {
+                let mut entries = Vec::new();
+                for name in names.iter() {
+                    let child = try!(self.lookup(&path::normalize(&path[], &name[])[]));
+                    entries.push(vfs::DirEntry {
+                        name: name.clone(),
+                        inode: child.inode,
+                        kind: child.file_type()
+                    });
+                }
+                Ok(entries)
+            },
+            _ => Err(errno::error(libc::ENOTDIR))
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+impl io::Streaming for TarFS {
+    fn do_write(&mut self, _handle: &io::Handle, _buf: &[u8]) -> IoResult<usize> {
+        Err(read_only())
+    }
+
+    fn do_read(&mut self, handle: &io::Handle, buf: &mut [u8]) -> IoResult<usize> {
+        let (path, pos) = {
+            let e = try!(self.get_open(handle));
+            (e.path.clone(), e.pos as usize)
+        };
+        let (offset, size) = match try!(self.lookup(&path[])).kind {
+            Kind::File {offset, size} => (offset, size),
+            _ => return Err(errno::error(libc::EISDIR))
+        };
+        let start = cmp::min(pos, size);
+        let len = cmp::min(buf.len(), size - start);
+        for i in range(0, len) {
+            buf[i] = self.data[offset + start + i];
+        }
+        try!(self.get_open(handle)).pos = (start + len) as u64;
+        Ok(len)
+    }
+
+    fn do_seek(&mut self, handle: &io::Handle, offset: i64, whence: SeekStyle) -> IoResult<u64> {
+        let (path, pos) = {
+            let e = try!(self.get_open(handle));
+            (e.path.clone(), e.pos)
+        };
+        let base = match whence {
+            SeekStyle::SeekSet => 0,
+            SeekStyle::SeekCur => pos as i64,
+            SeekStyle::SeekEnd => try!(self.lookup(&path[])).size() as i64
+        };
+        let new_pos = match base.checked_add(offset) {
+            Some(p) if p < 0 => return Err(errno::error(libc::EINVAL)),
+            Some(p) => p as u64,
+            None => return Err(errno::error(libc::EOVERFLOW))
+        };
+        try!(self.get_open(handle)).pos = new_pos;
+        Ok(new_pos)
+    }
+
+    fn do_close(&mut self, handle: &io::Handle) -> IoResult<()> {
+        match self.open_entries.remove(&handle.get_local_fd()) {
+            Some(_) => Ok(()),
+            None => Err(errno::error(libc::EBADF))
+        }
+    }
+}
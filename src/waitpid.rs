@@ -4,6 +4,7 @@ extern crate ptrace;
 extern crate "posix-ipc" as ipc;
 
 use self::ipc::signals;
+use error::{SandboxError, SandboxResult};
 
 use std::os;
 use std::num::FromPrimitive;
@@ -18,22 +19,21 @@ pub enum WaitState {
 }
 
 impl WaitState {
-    pub fn from_i32(v: i32) -> Self {
+    /// None if the status, or the signal in it, isn't one we know about
+    pub fn from_i32(v: i32) -> Option<Self> {
         if v & 0xff == 0x7f {
-            let sig = FromPrimitive::from_i32((v & 0xff00) >> 8).expect("Unknown signal");
-            let evt = ptrace::Event::from_wait_status(v);
-            match evt {
-                Option::Some(s) => WaitState::PTrace(s),
-                Option::None => WaitState::Stopped(sig)
+            match ptrace::Event::from_wait_status(v) {
+                Option::Some(s) => Some(WaitState::PTrace(s)),
+                Option::None => FromPrimitive::from_i32((v & 0xff00) >> 8).map(|sig| WaitState::Stopped(sig))
             }
         } else if v == 0xffff {
-            WaitState::Continued
+            Some(WaitState::Continued)
         } else if (v & 0xff00) >> 8 == 0 {
-            WaitState::Exited((v & 0xff) as isize)
+            Some(WaitState::Exited((v & 0xff) as isize))
         } else if (((v & 0x7f) + 1) >> 1) > 0 {
-            WaitState::Signaled(FromPrimitive::from_i32(v & 0x7f).expect("Unknown signal"))
+            FromPrimitive::from_i32(v & 0x7f).map(|sig| WaitState::Signaled(sig))
         } else {
-            panic! ("Unknown wait state: {:?}", v);
+            None
         }
     }
 }
@@ -54,7 +54,7 @@ bitflags! {
 }
 
 #[allow(unstable)]
-pub fn wait(pid: libc::pid_t, opts: Options) -> Result<WaitResult, usize> {
+pub fn wait(pid: libc::pid_t, opts: Options) -> SandboxResult<WaitResult> {
     let mut st: libc::c_int = 0;
     let r;
 
@@ -62,10 +62,12 @@ pub fn wait(pid: libc::pid_t, opts: Options) -> Result<WaitResult, usize> {
         r = ext::waitpid(pid, &mut st, opts.bits);
     }
 
-    if r >= 0 {
-        Result::Ok(WaitResult {pid: r, status: st, state: WaitState::from_i32(st)})
-    } else {
-        Result::Err(os::errno())
+    if r < 0 {
+        return Result::Err(SandboxError::Wait(os::errno()));
+    }
+    match WaitState::from_i32(st) {
+        Some(state) => Result::Ok(WaitResult {pid: r, status: st, state: state}),
+        None => Result::Err(SandboxError::UnknownWaitStatus(st))
     }
 }
 
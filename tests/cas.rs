@@ -0,0 +1,67 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::{Filesystem, errno};
+use sandbox::vfs::cas::CasFS;
+use sandbox::io::Handle;
+use std::io::{File, TempDir, SeekStyle};
+use std::i64;
+use common::{to_ref, open};
+
+mod common;
+
+static HELLO: &'static str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+fn store() -> TempDir {
+    let dir = TempDir::new("cas").unwrap();
+    File::create(&dir.path().join(HELLO)).write_str("hello").unwrap();
+    dir
+}
+
+fn manifest() -> Vec<(String, String)> {
+    vec![(String::from_str("app/hello.txt"), String::from_str(HELLO))]
+}
+
+#[test]
+fn serves_verified_blob() {
+    let dir = store();
+    let fs = CasFS::new(dir.path().clone(), &manifest()[]).unwrap();
+    let fs = to_ref(fs);
+
+    let mut h = open(&fs, "/app/hello.txt", vfs::ReadOnly, 0);
+    let mut buf = [0u8; 16];
+    assert!(h.read(&mut buf) == Ok(5));
+    assert!(&buf[..5] == b"hello");
+    assert!(fs.borrow().do_stat("/app").is_ok());
+}
+
+#[test]
+fn seek_bounds() {
+    let dir = store();
+    let fs = to_ref(CasFS::new(dir.path().clone(), &manifest()[]).unwrap());
+    let mut h = open(&fs, "/app/hello.txt", vfs::ReadOnly, 0);
+    let err = h.seek(-6, SeekStyle::SeekEnd).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EINVAL);
+    assert!(h.seek(i64::MAX, SeekStyle::SeekSet) == Ok(i64::MAX as u64));
+    let err = h.seek(1, SeekStyle::SeekCur).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EOVERFLOW);
+}
+
+#[test]
+fn rejects_tampered_blob() {
+    let dir = store();
+    let mut fs = CasFS::new(dir.path().clone(), &manifest()[]).unwrap();
+    File::create(&dir.path().join(HELLO)).write_str("jello").unwrap();
+
+    let err = fs.do_open("/app/hello.txt", vfs::ReadOnly, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EIO);
+}
+
+#[test]
+fn rejects_bad_hashes() {
+    let dir = store();
+    let bad = vec![(String::from_str("x"), String::from_str("../../etc/passwd"))];
+    assert!(CasFS::new(dir.path().clone(), &bad[]).is_err());
+}
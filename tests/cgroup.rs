@@ -0,0 +1,84 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+
+use sandbox::cgroup::{Cgroup, CgroupConfig};
+use std::io::{File, TempDir};
+
+#[test]
+fn missing_parent() {
+    let config = CgroupConfig::new(Path::new("/nonexistent/cgroup"));
+    assert!(Cgroup::create(&config, "sandbox-test").is_err());
+}
+
+#[test]
+fn not_a_cgroup() {
+    // A plain directory has none of the files a cgroup's limits go in
+    let dir = TempDir::new("cgroup").unwrap();
+    let mut config = CgroupConfig::new(dir.path().clone());
+    config.pids_max = Some(16);
+    assert!(Cgroup::create(&config, "sandbox-test").is_err());
+    // The half made group is cleaned up
+    assert!(!dir.path().join("sandbox-test").exists());
+    // Nor is the parent given control files it lacked
+    assert!(!dir.path().join("cgroup.subtree_control").exists());
+}
+
+#[test]
+fn add_needs_procs_file() {
+    let dir = TempDir::new("cgroup").unwrap();
+    let config = CgroupConfig::new(dir.path().clone());
+    let cgroup = Cgroup::create(&config, "sandbox-test").unwrap();
+    assert!(cgroup.add(1).is_err());
+    assert!(!cgroup.get_path().join("cgroup.procs").exists());
+}
+
+/// A group made in a plain directory, with the files a kernel would fill
+/// in written by hand
+fn fixture(dir: &TempDir, files: &[(&str, &str)]) -> Cgroup {
+    let config = CgroupConfig::new(dir.path().clone());
+    let cgroup = Cgroup::create(&config, "sandbox-test").unwrap();
+    for &(name, contents) in files.iter() {
+        File::create(&cgroup.get_path().join(name)).write_str(contents).unwrap();
+    }
+    cgroup
+}
+
+#[test]
+fn usage_without_controllers() {
+    let dir = TempDir::new("cgroup").unwrap();
+    let cgroup = fixture(&dir, &[
+        ("cpu.stat", "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n")
+    ]);
+    let usage = cgroup.usage().unwrap();
+    assert!(usage.cpu_usec == 1500);
+    assert!(usage.memory_bytes.is_none() && usage.memory_peak.is_none());
+    assert!(usage.oom_kills.is_none() && usage.pids.is_none());
+}
+
+#[test]
+fn usage_with_controllers() {
+    let dir = TempDir::new("cgroup").unwrap();
+    let mut cgroup = fixture(&dir, &[
+        ("cpu.stat", "usage_usec 42\n"),
+        ("memory.current", "4096\n"),
+        ("memory.events", "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n"),
+        ("pids.current", "2\n")
+    ]);
+    let usage = cgroup.usage().unwrap();
+    assert!(usage.cpu_usec == 42);
+    assert!(usage.memory_bytes == Some(4096));
+    assert!(usage.memory_peak.is_none());
+    assert!(usage.oom_kills == Some(1));
+    assert!(usage.pids == Some(2));
+
+    // Each OOM kill is only reported once
+    assert!(cgroup.check_oom_kill());
+    assert!(!cgroup.check_oom_kill());
+}
+
+#[test]
+fn usage_needs_cpu_stat() {
+    let dir = TempDir::new("cgroup").unwrap();
+    let cgroup = fixture(&dir, &[]);
+    assert!(cgroup.usage().is_err());
+}
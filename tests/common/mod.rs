@@ -0,0 +1,39 @@
+//! Fixtures shared by the filesystem tests
+
+#![allow(dead_code)]
+
+use sandbox::vfs;
+use sandbox::vfs::{Filesystem, FsRef};
+use sandbox::io::Handle;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// Shares fs the way the VFS does once it's mounted
+pub fn to_ref<F: Filesystem + 'static>(fs: F) -> FsRef<'static> {
+    Rc::new(RefCell::new(Box::new(fs) as Box<Filesystem>))
+}
+
+pub fn open(fs: &FsRef<'static>, path: &str, flags: vfs::OpenFlags, mode: u32) -> vfs::Handle<'static> {
+    let local = fs.borrow_mut().do_open(path, flags, mode).ok().expect("open failed");
+    vfs::Handle::new(fs.clone(), vfs::FIRST_VIRTUAL_FD, local, String::from_str(path))
+}
+
+pub fn read_all(fs: &FsRef<'static>, path: &str) -> String {
+    let mut h = open(fs, path, vfs::ReadOnly, 0);
+    let mut contents = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        match h.read(&mut buf).unwrap() {
+            0 => break,
+            len => contents.push_all(&buf[..len])
+        }
+    }
+    h.close().unwrap();
+    String::from_utf8(contents).unwrap()
+}
+
+/// The names in the directory at path
+pub fn list(fs: &FsRef<'static>, path: &str) -> Vec<String> {
+    let h = open(fs, path, vfs::ReadOnly | vfs::Directory, 0);
+    h.readdir().unwrap().into_iter().map(|e| e.name).collect()
+}
@@ -0,0 +1,61 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+
+use sandbox::vfs::{DirEntry, linux_dirent, linux_dirent64};
+use std::io::FileType;
+
+fn entry(name: &str, kind: FileType) -> DirEntry {
+    DirEntry {name: String::from_str(name), inode: 0x0102030405060708, kind: kind}
+}
+
+fn le(buf: &[u8]) -> u64 {
+    buf.iter().rev().fold(0u64, |v, b| (v << 8) | *b as u64)
+}
+
+#[test]
+fn dirent64_layout() {
+    let rec = linux_dirent64(&entry("a", FileType::RegularFile), 7);
+    // d_ino, d_off, d_reclen, d_type, then the name and its NUL
+    assert!(rec.len() == 24);
+    assert!(le(&rec[0..8]) == 0x0102030405060708);
+    assert!(le(&rec[8..16]) == 7);
+    assert!(le(&rec[16..18]) == 24);
+    // DT_REG
+    assert!(rec[18] == 8);
+    assert!(&rec[19..20] == b"a");
+    assert!(rec[20..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn dirent_layout() {
+    let rec = linux_dirent(&entry("a", FileType::Directory), 7);
+    // d_ino, d_off, d_reclen, the name and its NUL, then d_type last
+    assert!(rec.len() == 24);
+    assert!(le(&rec[0..8]) == 0x0102030405060708);
+    assert!(le(&rec[8..16]) == 7);
+    assert!(le(&rec[16..18]) == 24);
+    assert!(&rec[18..19] == b"a");
+    assert!(rec[19..23].iter().all(|b| *b == 0));
+    // DT_DIR
+    assert!(rec[23] == 4);
+}
+
+#[test]
+fn records_aligned() {
+    for len in range(1, 40) {
+        let name: String = range(0, len).map(|_| 'x').collect();
+        for kind in [FileType::Symlink, FileType::Unknown].iter() {
+            let rec = linux_dirent64(&entry(&name[], *kind), 1);
+            assert!(rec.len() % 8 == 0 && rec.len() >= 19 + len + 1);
+            assert!(le(&rec[16..18]) == rec.len() as u64);
+            assert!(rec[19 + len] == 0);
+            let rec = linux_dirent(&entry(&name[], *kind), 1);
+            assert!(rec.len() % 8 == 0 && rec.len() >= 18 + len + 2);
+            assert!(le(&rec[16..18]) == rec.len() as u64);
+            assert!(rec[18 + len] == 0);
+        }
+    }
+    // DT_LNK and DT_UNKNOWN
+    assert!(linux_dirent64(&entry("l", FileType::Symlink), 1)[18] == 10);
+    assert!(*linux_dirent(&entry("u", FileType::Unknown), 1).last().unwrap() == 0);
+}
@@ -0,0 +1,13 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+
+use sandbox::SandboxError;
+
+#[test]
+fn vanished_tasks() {
+    // ESRCH and EPERM on Linux
+    assert!(SandboxError::Ptrace(3).is_vanished());
+    assert!(!SandboxError::Ptrace(1).is_vanished());
+    assert!(!SandboxError::Wait(3).is_vanished());
+    assert!(!SandboxError::UnknownWaitStatus(0x137f).is_vanished());
+}
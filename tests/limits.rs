@@ -0,0 +1,38 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate "posix-ipc" as ipc;
+
+use sandbox::limits::{Limits, Limit};
+use ipc::signals::Signal;
+
+#[test]
+fn limit_signals() {
+    assert!(Limit::from_signal(Signal::Xcpu) == Some(Limit::Cpu));
+    assert!(Limit::from_signal(Signal::Xfsz) == Some(Limit::FileSize));
+    assert!(Limit::from_signal(Signal::Kill) == None);
+}
+
+#[test]
+fn unlimited_by_default() {
+    let limits: Limits = Default::default();
+    assert!(limits.cpu_seconds.is_none() && limits.address_space.is_none());
+    // Applying no limits changes nothing, so it works in the test process
+    assert!(limits.apply().is_ok());
+}
+
+#[test]
+fn limit_only_from_kernel() {
+    // SI_USER and SI_KERNEL on Linux
+    assert!(Limit::from_siginfo(Signal::Xcpu, 0x80, 0, 100) == Some(Limit::Cpu));
+    assert!(Limit::from_siginfo(Signal::Xcpu, 0, 100, 100) == None);
+    assert!(Limit::from_siginfo(Signal::Xfsz, 0, 100, 100) == Some(Limit::FileSize));
+    assert!(Limit::from_siginfo(Signal::Xfsz, 0, 101, 100) == None);
+    assert!(Limit::from_siginfo(Signal::Kill, 0x80, 0, 100) == None);
+}
+
+#[test]
+fn cpu_limit_saturates() {
+    let limits = Limits {cpu_seconds: Some(!0), ..Default::default()};
+    // RLIM_INFINITY, which the test process already has
+    assert!(limits.apply().is_ok());
+}
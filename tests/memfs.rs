@@ -0,0 +1,125 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::errno;
+use sandbox::vfs::mem::MemFS;
+use sandbox::io::Handle;
+use std::io::{FileType, SeekStyle};
+use std::i64;
+use common::{to_ref, open, list};
+
+mod common;
+
+#[test]
+fn write_then_read_back() {
+    let fs = to_ref(MemFS::new());
+    let mut h = open(&fs, "/hello", vfs::Create | vfs::ReadWrite, 0o644);
+    assert!(h.write(b"hello world") == Ok(11));
+    assert!(h.seek(6, SeekStyle::SeekSet) == Ok(6));
+    let mut buf = [0u8; 16];
+    assert!(h.read(&mut buf) == Ok(5));
+    assert!(&buf[..5] == b"world");
+    assert!(h.stat().unwrap().size == 11);
+    h.close().unwrap();
+}
+
+#[test]
+fn directories_and_listing() {
+    let mut mem = MemFS::new();
+    mem.create_dir("/tmp", 0o755).unwrap();
+    mem.create_file("/tmp/a", b"a", 0o644).unwrap();
+    mem.create_symlink("/tmp/b", "a").unwrap();
+    let fs = to_ref(mem);
+
+    assert!(fs.borrow().do_stat("/tmp").unwrap().kind == FileType::Directory);
+    assert!(fs.borrow().do_readlink("/tmp/b").unwrap() == "a");
+
+    assert!(list(&fs, "/tmp") == vec![String::from_str("a"), String::from_str("b")]);
+}
+
+#[test]
+fn missing_paths() {
+    let fs = to_ref(MemFS::new());
+    let err = fs.borrow().do_stat("/nope").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ENOENT);
+    let err = fs.borrow_mut().do_open("/nope/file", vfs::Create | vfs::WriteOnly, 0o644).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ENOENT);
+}
+
+#[test]
+fn quota_limits_writes() {
+    let fs = to_ref(MemFS::with_quota(8));
+    let mut h = open(&fs, "/big", vfs::Create | vfs::WriteOnly, 0o644);
+    assert!(h.write(b"12345678") == Ok(8));
+    let err = h.write(b"9").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ENOSPC);
+
+    let mut h = open(&fs, "/big", vfs::Truncate | vfs::WriteOnly, 0o644);
+    assert!(h.write(b"9") == Ok(1));
+}
+
+#[test]
+fn unlinked_file_lives_until_closed() {
+    let fs = to_ref(MemFS::new());
+    let mut h = open(&fs, "/scratch", vfs::Create | vfs::ReadWrite, 0o644);
+    assert!(h.write(b"data") == Ok(4));
+    fs.borrow_mut().do_unlink("/scratch").unwrap();
+    assert!(fs.borrow().do_stat("/scratch").is_err());
+
+    assert!(h.seek(0, SeekStyle::SeekSet) == Ok(0));
+    let mut buf = [0u8; 4];
+    assert!(h.read(&mut buf) == Ok(4));
+    h.close().unwrap();
+}
+
+#[test]
+fn rename_and_rmdir() {
+    let mut mem = MemFS::new();
+    mem.create_dir("/a", 0o755).unwrap();
+    mem.create_file("/a/f", b"x", 0o644).unwrap();
+    let fs = to_ref(mem);
+
+    let err = fs.borrow_mut().do_rmdir("/a").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ENOTEMPTY);
+    let err = fs.borrow_mut().do_rename("/a", "/a/b").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EINVAL);
+
+    fs.borrow_mut().do_mkdir("/b", 0o700).unwrap();
+    fs.borrow_mut().do_rename("/a/f", "/b/g").unwrap();
+    fs.borrow_mut().do_rmdir("/a").unwrap();
+    assert!(fs.borrow().do_stat("/b/g").unwrap().size == 1);
+    assert!(fs.borrow().do_stat("/a").is_err());
+}
+
+#[test]
+fn file_size_capped() {
+    let mut mem = MemFS::new();
+    mem.set_max_file_size(8);
+    let fs = to_ref(mem);
+    let mut h = open(&fs, "/capped", vfs::Create | vfs::ReadWrite, 0o644);
+    assert!(h.write(b"12345678") == Ok(8));
+    let err = h.write(b"9").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EFBIG);
+
+    // Neither a sparse write nor a truncate allocates past the cap
+    assert!(h.seek(1 << 40, SeekStyle::SeekSet) == Ok(1 << 40));
+    let err = h.write(b"x").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EFBIG);
+    let err = h.truncate(1 << 40).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EFBIG);
+    assert!(h.stat().unwrap().size == 8);
+}
+
+#[test]
+fn seek_bounds() {
+    let fs = to_ref(MemFS::new());
+    let mut h = open(&fs, "/f", vfs::Create | vfs::ReadWrite, 0o644);
+    assert!(h.write(b"data") == Ok(4));
+    let err = h.seek(-5, SeekStyle::SeekEnd).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EINVAL);
+    assert!(h.seek(i64::MAX, SeekStyle::SeekSet) == Ok(i64::MAX as u64));
+    let err = h.seek(1, SeekStyle::SeekCur).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EOVERFLOW);
+}
@@ -0,0 +1,65 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+
+use sandbox::vfs::FsRef;
+use sandbox::vfs::mount::MountTable;
+use sandbox::vfs::mem::MemFS;
+use common::{to_ref, list};
+
+mod common;
+
+/// A filesystem holding a single file named after it, so lookups can tell
+/// which one they found
+fn named(name: &str) -> FsRef<'static> {
+    let mut fs = MemFS::new();
+    fs.create_file(&format!("/{}", name)[], b"", 0o644).unwrap();
+    to_ref(fs)
+}
+
+fn lookup(mounts: &MountTable<'static>, path: &str) -> (String, String) {
+    let (local, fs) = mounts.resolve(path).expect("nothing mounted");
+    let name = list(&fs, "/").into_iter().next().unwrap();
+    (local, name)
+}
+
+#[test]
+fn whole_components_only() {
+    let mut mounts = MountTable::new();
+    mounts.mount("/", named("root"));
+    mounts.mount("/foo", named("foo"));
+    assert!(lookup(&mounts, "/foo/x") == (String::from_str("/x"), String::from_str("foo")));
+    assert!(lookup(&mounts, "/foo") == (String::from_str("/"), String::from_str("foo")));
+    assert!(lookup(&mounts, "/foobar") == (String::from_str("/foobar"), String::from_str("root")));
+    assert!(lookup(&mounts, "/foobar/x") == (String::from_str("/foobar/x"), String::from_str("root")));
+}
+
+#[test]
+fn nested_mounts() {
+    let mut mounts = MountTable::new();
+    // Mounted shallowest last, which mustn't matter
+    mounts.mount("/a/b", named("b"));
+    mounts.mount("/a", named("a"));
+    mounts.mount("/", named("root"));
+    assert!(lookup(&mounts, "/a/b/c") == (String::from_str("/c"), String::from_str("b")));
+    assert!(lookup(&mounts, "/a/c") == (String::from_str("/c"), String::from_str("a")));
+    assert!(lookup(&mounts, "/c") == (String::from_str("/c"), String::from_str("root")));
+
+    assert!(mounts.unmount("/a/b/").is_some());
+    assert!(lookup(&mounts, "/a/b/c") == (String::from_str("/b/c"), String::from_str("a")));
+}
+
+#[test]
+fn root_fallback() {
+    let mut mounts = MountTable::new();
+    mounts.mount("/app", named("app"));
+    assert!(mounts.resolve("/etc/passwd").is_none());
+    mounts.mount("/", named("root"));
+    assert!(lookup(&mounts, "/etc/passwd") == (String::from_str("/etc/passwd"), String::from_str("root")));
+    assert!(lookup(&mounts, "/") == (String::from_str("/"), String::from_str("root")));
+
+    // Mounting again replaces what was there
+    mounts.mount("//", named("other"));
+    assert!(lookup(&mounts, "/") == (String::from_str("/"), String::from_str("other")));
+    assert!(mounts.unmount("/").is_some());
+    assert!(mounts.unmount("/").is_none());
+}
@@ -0,0 +1,107 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::{Filesystem, VFS, AT_FDCWD, errno};
+use sandbox::vfs::native::NativeFS;
+use sandbox::io::Handle;
+use std::io::{File, TempDir, USER_RWX, USER_READ, USER_WRITE};
+use std::io::fs;
+use common::{to_ref, open};
+
+mod common;
+
+#[test]
+fn write_and_truncate() {
+    let dir = TempDir::new("native").unwrap();
+    let fs = to_ref(NativeFS::new(dir.path().clone()));
+
+    let mut h = open(&fs, "/out.txt", vfs::Create | vfs::WriteOnly, 0o600);
+    assert!(h.write(b"hello world") == Ok(11));
+    h.truncate(5).unwrap();
+    h.sync(false).unwrap();
+    h.close().unwrap();
+
+    let host = dir.path().join("out.txt");
+    assert!(File::open(&host).read_to_string().unwrap() == "hello");
+    assert!(fs::stat(&host).unwrap().perm & USER_RWX == USER_READ | USER_WRITE);
+
+    // The host file is gone from the fd map once closed
+    let err = h.close().unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EBADF);
+}
+
+#[test]
+fn read_only_mount() {
+    let dir = TempDir::new("native").unwrap();
+    File::create(&dir.path().join("ro.txt")).write_str("data").unwrap();
+    let mut native = NativeFS::read_only(dir.path().clone());
+
+    let err = native.do_open("/ro.txt", vfs::WriteOnly, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EROFS);
+    let err = native.do_open("/new.txt", vfs::Create | vfs::WriteOnly, 0o644).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EROFS);
+    assert!(native.do_open("/ro.txt", vfs::ReadOnly, 0).is_ok());
+    // O_CREAT only matters if the file is missing
+    assert!(native.do_open("/ro.txt", vfs::ReadOnly | vfs::Create, 0o644).is_ok());
+    assert!(!dir.path().join("new.txt").exists());
+}
+
+#[test]
+fn read_only_mount_refuses_truncate() {
+    let dir = TempDir::new("native").unwrap();
+    let host = dir.path().join("ro.txt");
+    File::create(&host).write_str("data").unwrap();
+    let mut native = NativeFS::read_only(dir.path().clone());
+
+    let err = native.do_open("/ro.txt", vfs::ReadOnly | vfs::Truncate, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EROFS);
+    assert!(File::open(&host).read_to_string().unwrap() == "data");
+}
+
+#[test]
+fn no_host_symlinks() {
+    let dir = TempDir::new("native").unwrap();
+    let mut native = NativeFS::new(dir.path().clone());
+    let err = native.do_symlink("/etc/passwd", "/escape").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EPERM);
+    assert!(!dir.path().join("escape").exists());
+}
+
+#[test]
+fn create_read_only() {
+    let dir = TempDir::new("native").unwrap();
+    let fs = to_ref(NativeFS::new(dir.path().clone()));
+    let mut h = open(&fs, "/new.txt", vfs::Create | vfs::ReadOnly, 0o644);
+    h.close().unwrap();
+    assert!(dir.path().join("new.txt").exists());
+}
+
+#[test]
+fn truncate_then_append() {
+    let dir = TempDir::new("native").unwrap();
+    let host = dir.path().join("log.txt");
+    File::create(&host).write_str("old contents").unwrap();
+    let fs = to_ref(NativeFS::new(dir.path().clone()));
+
+    let mut h = open(&fs, "/log.txt", vfs::WriteOnly | vfs::Truncate | vfs::Append, 0);
+    assert!(h.write(b"new") == Ok(3));
+    h.close().unwrap();
+    let mut h = open(&fs, "/log.txt", vfs::WriteOnly | vfs::Append, 0);
+    assert!(h.write(b"+more") == Ok(5));
+    h.close().unwrap();
+    assert!(File::open(&host).read_to_string().unwrap() == "new+more");
+}
+
+#[test]
+fn create_applies_umask() {
+    let dir = TempDir::new("native").unwrap();
+    let mut vfs = VFS::new();
+    vfs.mount_filesystem("/", Box::new(NativeFS::new(dir.path().clone())));
+    vfs.open(AT_FDCWD, "/a.txt", vfs::Create | vfs::WriteOnly, 0o666).unwrap();
+    assert!(fs::stat(&dir.path().join("a.txt")).unwrap().perm.bits() == 0o644);
+    vfs.set_umask(0o077);
+    vfs.open(AT_FDCWD, "/b.txt", vfs::Create | vfs::WriteOnly, 0o666).unwrap();
+    assert!(fs::stat(&dir.path().join("b.txt")).unwrap().perm.bits() == 0o600);
+}
@@ -0,0 +1,81 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::{FsRef, errno};
+use sandbox::vfs::mem::MemFS;
+use sandbox::vfs::overlay::OverlayFS;
+use sandbox::io::Handle;
+use common::{to_ref, open, read_all, list};
+
+mod common;
+
+fn layers() -> (FsRef<'static>, FsRef<'static>) {
+    let mut lower = MemFS::new();
+    lower.create_dir("/etc", 0o755).unwrap();
+    lower.create_file("/etc/motd", b"lower", 0o644).unwrap();
+    lower.create_file("/etc/hosts", b"localhost", 0o644).unwrap();
+    let mut upper = MemFS::new();
+    upper.create_dir("/etc", 0o755).unwrap();
+    (to_ref(upper), to_ref(lower))
+}
+
+#[test]
+fn reads_fall_through() {
+    let (upper, lower) = layers();
+    let fs = to_ref(OverlayFS::new(upper, lower));
+    assert!(read_all(&fs, "/etc/motd") == "lower");
+}
+
+#[test]
+fn writes_copy_up() {
+    let (upper, lower) = layers();
+    let fs = to_ref(OverlayFS::new(upper.clone(), lower.clone()));
+
+    let mut h = open(&fs, "/etc/motd", vfs::WriteOnly | vfs::Append, 0);
+    assert!(h.write(b"+upper") == Ok(6));
+    h.close().unwrap();
+
+    assert!(read_all(&fs, "/etc/motd") == "lower+upper");
+    assert!(read_all(&upper, "/etc/motd") == "lower+upper");
+    assert!(read_all(&lower, "/etc/motd") == "lower");
+}
+
+#[test]
+fn whiteouts_hide_lower_files() {
+    let (upper, lower) = layers();
+    let mut overlay = OverlayFS::new(upper, lower);
+    overlay.whiteout("/etc/hosts");
+    let fs = to_ref(overlay);
+
+    let err = fs.borrow().do_stat("/etc/hosts").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ENOENT);
+
+    assert!(list(&fs, "/etc") == vec![String::from_str("motd")]);
+}
+
+#[test]
+fn unlink_records_whiteout() {
+    let (upper, lower) = layers();
+    let fs = to_ref(OverlayFS::new(upper, lower.clone()));
+    fs.borrow_mut().do_unlink("/etc/hosts").unwrap();
+    assert!(fs.borrow().do_stat("/etc/hosts").is_err());
+    assert!(lower.borrow().do_stat("/etc/hosts").is_ok());
+}
+
+#[test]
+fn copy_up_creates_parents() {
+    let mut lower = MemFS::new();
+    lower.create_dir("/var", 0o755).unwrap();
+    lower.create_dir("/var/log", 0o750).unwrap();
+    let upper = to_ref(MemFS::new());
+    let fs = to_ref(OverlayFS::new(upper.clone(), to_ref(lower)));
+
+    let mut h = open(&fs, "/var/log/app.log", vfs::Create | vfs::WriteOnly, 0o644);
+    assert!(h.write(b"started") == Ok(7));
+    h.close().unwrap();
+
+    assert!(upper.borrow().do_stat("/var/log").unwrap().perm.bits() == 0o750);
+    assert!(read_all(&fs, "/var/log/app.log") == "started");
+}
@@ -0,0 +1,64 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::{VFS, AT_FDCWD, errno};
+use sandbox::vfs::mem::MemFS;
+
+fn tree() -> VFS<'static> {
+    let mut root = MemFS::new();
+    root.create_dir("/app", 0o755).unwrap();
+    root.create_dir("/app/lib", 0o755).unwrap();
+    root.create_file("/app/main.js", b"main", 0o644).unwrap();
+    root.create_file("/app/lib/util.js", b"util", 0o644).unwrap();
+    root.create_file("/main.js", b"root", 0o644).unwrap();
+    let mut vfs = VFS::new();
+    vfs.mount_filesystem("/", Box::new(root));
+    vfs
+}
+
+fn path_of(vfs: &mut VFS, fd: i32) -> String {
+    String::from_str(vfs.get_handle(fd).unwrap().get_path())
+}
+
+#[test]
+fn relative_to_cwd() {
+    let mut vfs = tree();
+    vfs.set_cwd("/app");
+    let fd = vfs.open(AT_FDCWD, "main.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/app/main.js");
+    let fd = vfs.open(AT_FDCWD, "lib/../../main.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/main.js");
+}
+
+#[test]
+fn relative_to_dirfd() {
+    let mut vfs = tree();
+    let dir = vfs.open(AT_FDCWD, "/app/lib", vfs::ReadOnly | vfs::Directory, 0).unwrap();
+    let fd = vfs.open(dir, "util.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/app/lib/util.js");
+    let fd = vfs.open(dir, "../main.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/app/main.js");
+
+    // Unaffected by the cwd, and gone once closed
+    vfs.set_cwd("/");
+    let fd = vfs.open(dir, "util.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/app/lib/util.js");
+    vfs.close(dir).unwrap();
+    let err = vfs.open(dir, "util.js", vfs::ReadOnly, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EBADF);
+}
+
+#[test]
+fn absolute_ignores_dirfd() {
+    let mut vfs = tree();
+    let dir = vfs.open(AT_FDCWD, "/app/lib", vfs::ReadOnly | vfs::Directory, 0).unwrap();
+    let fd = vfs.open(dir, "/main.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/main.js");
+    // Even a dirfd that isn't open, or is one of the host's
+    let fd = vfs.open(12345, "/app/main.js", vfs::ReadOnly, 0).unwrap();
+    assert!(path_of(&mut vfs, fd) == "/app/main.js");
+    let err = vfs.open(3, "main.js", vfs::ReadOnly, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EBADF);
+}
@@ -0,0 +1,45 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate seccomp;
+
+use sandbox::policy::{Policy, Action};
+use seccomp::Syscall;
+
+#[test]
+fn empty_policy_kills() {
+    let p = Policy::new();
+    assert!(p.get_default() == Action::Kill);
+    assert!(p.rules().len() == 0);
+}
+
+#[test]
+fn add_rules() {
+    let mut p = Policy::new();
+    p.allow(Syscall::GETPID).errno(Syscall::SOCKET, 1);
+    assert!(p.rules().len() == 2);
+    assert!(p.rules()[0].action == Action::Allow);
+    assert!(p.rules()[1].action == Action::Errno(1));
+}
+
+#[test]
+fn default_traces_open() {
+    let p = Policy::default();
+    let mut found = false;
+    for rule in p.rules().iter() {
+        match rule.call {
+            Syscall::OPEN => {
+                assert!(rule.action == Action::Trace);
+                found = true;
+            },
+            _ => {}
+        }
+    }
+    assert!(found);
+}
+
+#[test]
+fn errno_default() {
+    let mut p = Policy::new();
+    p.set_default(Action::Errno(38));
+    assert!(p.get_default() == Action::Errno(38));
+}
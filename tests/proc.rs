@@ -0,0 +1,54 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::{FsRef, errno};
+use sandbox::vfs::proc::{ProcFS, ProcInfo};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::FileType;
+use common::{to_ref, open};
+
+mod common;
+
+fn procfs() -> FsRef<'static> {
+    let mut info = ProcInfo::new();
+    info.exe = String::from_str("/app/node");
+    info.cwd = String::from_str("/app");
+    info.fds.insert(4098, String::from_str("/app/index.js"));
+    info.fds.insert(4100, String::from_str("/tmp/log"));
+    let fs = ProcFS::new(Rc::new(RefCell::new(info)));
+    to_ref(fs)
+}
+
+#[test]
+fn readlink_self() {
+    let fs = procfs();
+    assert!(fs.borrow().do_readlink("/self/exe").unwrap() == "/app/node");
+    assert!(fs.borrow().do_readlink("/self/cwd").unwrap() == "/app");
+    assert!(fs.borrow().do_readlink("/self/fd/4100").unwrap() == "/tmp/log");
+    let err = fs.borrow().do_readlink("/self/fd/4099").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ENOENT);
+    let err = fs.borrow().do_readlink("/self/fd").unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EINVAL);
+}
+
+#[test]
+fn readdir_fds() {
+    let fs = procfs();
+    let h = open(&fs, "/self/fd", vfs::ReadOnly | vfs::Directory, 0);
+    let entries = h.readdir().unwrap();
+    let names: Vec<&str> = entries.iter().map(|e| &e.name[]).collect();
+    assert!(names == vec!["4098", "4100"]);
+    assert!(entries.iter().all(|e| e.kind == FileType::Symlink));
+    assert!(entries[0].inode != entries[1].inode);
+}
+
+#[test]
+fn read_only() {
+    let fs = procfs();
+    let err = fs.borrow_mut().do_open("/self/maps", vfs::WriteOnly, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EROFS);
+    assert!(fs.borrow().is_read_only());
+}
@@ -0,0 +1,84 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs::{VFS, errno};
+use sandbox::vfs::mem::MemFS;
+
+/// A root filesystem and another mounted at /mnt, with links within and
+/// between them
+fn linked() -> VFS<'static> {
+    let mut root = MemFS::new();
+    root.create_dir("/app", 0o755).unwrap();
+    root.create_file("/app/main.js", b"", 0o644).unwrap();
+    root.create_symlink("/app/index.js", "main.js").unwrap();
+    root.create_dir("/usr", 0o755).unwrap();
+    root.create_dir("/usr/lib", 0o755).unwrap();
+    root.create_symlink("/app/lib", "/usr/lib").unwrap();
+    root.create_symlink("/app/data", "../mnt/data").unwrap();
+    root.create_dir("/mnt", 0o755).unwrap();
+    root.create_symlink("/loop", "/loop").unwrap();
+
+    let mut mnt = MemFS::new();
+    mnt.create_dir("/data", 0o755).unwrap();
+    mnt.create_symlink("/data/current", "/app").unwrap();
+
+    let mut vfs = VFS::new();
+    vfs.mount_filesystem("/", Box::new(root));
+    vfs.mount_filesystem("/mnt", Box::new(mnt));
+    vfs
+}
+
+#[test]
+fn relative_link() {
+    let vfs = linked();
+    assert!(vfs.resolve("/app/index.js", true).unwrap() == "/app/main.js");
+    assert!(vfs.resolve("/app/index.js", false).unwrap() == "/app/index.js");
+}
+
+#[test]
+fn absolute_link() {
+    let vfs = linked();
+    assert!(vfs.resolve("/app/lib/libc.so", true).unwrap() == "/usr/lib/libc.so");
+    // Only the final component is left alone
+    assert!(vfs.resolve("/app/lib/libc.so", false).unwrap() == "/usr/lib/libc.so");
+}
+
+#[test]
+fn links_across_mounts() {
+    let vfs = linked();
+    assert!(vfs.resolve("/app/data", true).unwrap() == "/mnt/data");
+    assert!(vfs.resolve("/app/data/current/main.js", true).unwrap() == "/app/main.js");
+}
+
+#[test]
+fn parent_of_link_target() {
+    let mut vfs = linked();
+    assert!(vfs.resolve("/app/lib/../bin", true).unwrap() == "/usr/bin");
+    assert!(vfs.resolve("/app/./lib/..", true).unwrap() == "/usr");
+    vfs.set_cwd("/app");
+    assert!(vfs.resolve("lib/../share", true).unwrap() == "/usr/share");
+    assert!(vfs.resolve("../..", true).unwrap() == "/");
+}
+
+#[test]
+fn too_many_links() {
+    let mut root = MemFS::new();
+    root.create_dir("/end", 0o755).unwrap();
+    // A chain of MAX_SYMLINKS links from /l1, and one more from /l0
+    for i in range(0, 40) {
+        root.create_symlink(&format!("/l{}", i)[], &format!("/l{}", i + 1)[]).unwrap();
+    }
+    root.create_symlink("/l40", "/end").unwrap();
+    let mut vfs = VFS::new();
+    vfs.mount_filesystem("/", Box::new(root));
+
+    assert!(vfs.resolve("/l1", true).unwrap() == "/end");
+    let err = vfs.resolve("/l0", true).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ELOOP);
+    assert!(vfs.resolve("/l0", false).unwrap() == "/l0");
+
+    let vfs = linked();
+    let err = vfs.resolve("/loop/x", false).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::ELOOP);
+}
@@ -0,0 +1,256 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate "posix-ipc" as ipc;
+extern crate seccomp;
+extern crate libc;
+
+use sandbox::events::{self, State, TimeoutKind};
+use sandbox::policy::Action;
+use sandbox::limits::{Limits, Limit};
+use sandbox::{Sandbox, Policy, SandboxError};
+use ipc::signals::Signal;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::Duration;
+use std::io::{timer, TempDir};
+use std::os;
+
+/// A sandbox running argv that lets every syscall through, and the states
+/// of the events it has seen
+fn sandbox<'a>(argv: &'a [&'a str]) -> (Sandbox<'a, 'a>, Rc<RefCell<Vec<State>>>) {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    let watcher = events::ClosureWatcher::new(Box::new(move |event: &events::Event| {
+        match event.state {
+            State::EnteredMain | State::PTrace(_) | State::Signal(_) |
+            State::ThreadCreated(_) | State::ProcessCreated(_) => {
+                let _ = event.cont();
+            },
+            _ => {}
+        }
+        log.borrow_mut().push(event.state);
+    }));
+    let mut policy = Policy::new();
+    policy.set_default(Action::Allow);
+    let exec = sandbox::executors::Execv::new(argv);
+    (Sandbox::new(Box::new(exec), Box::new(watcher), policy), seen)
+}
+
+fn run(sbox: &mut Sandbox) {
+    while sbox.is_running() {
+        sbox.tick().ok().expect("Could not wait for sandbox events");
+    }
+}
+
+fn timed_out(seen: &Vec<State>, kind: TimeoutKind) -> bool {
+    seen.iter().any(|s| match *s {
+        State::Timeout(k) => k == kind,
+        _ => false
+    })
+}
+
+fn killed(seen: &Vec<State>) -> bool {
+    seen.iter().any(|s| match *s {
+        State::Killed(Signal::Kill) => true,
+        _ => false
+    })
+}
+
+#[test]
+fn no_timeout_until_spawned() {
+    let argv = ["/bin/true"];
+    let (mut sbox, _) = sandbox(&argv);
+    sbox.set_deadline(Some(Duration::milliseconds(100)));
+    assert!(sbox.next_timeout().is_none());
+}
+
+#[test]
+fn deadline_kills_sleeping_child() {
+    let argv = ["/bin/sleep", "60"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_deadline(Some(Duration::milliseconds(100)));
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    let due = sbox.next_timeout().expect("No timeout pending");
+    assert!(due <= Duration::milliseconds(100));
+    run(&mut sbox);
+    assert!(timed_out(&*seen.borrow(), TimeoutKind::Deadline));
+    assert!(killed(&*seen.borrow()));
+    assert!(sbox.next_timeout().is_none());
+}
+
+#[test]
+fn idle_timeout_kills_sleeping_child() {
+    let argv = ["/bin/sleep", "60"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_idle_timeout(Some(Duration::milliseconds(100)));
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(timed_out(&*seen.borrow(), TimeoutKind::Idle));
+    assert!(killed(&*seen.borrow()));
+}
+
+#[test]
+fn deadline_kills_every_task() {
+    // The root waits on its children, so it only ends once they all have
+    let argv = ["/bin/sh", "-c", "sleep 60 & sleep 60 & wait"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_deadline(Some(Duration::milliseconds(200)));
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(timed_out(&*seen.borrow(), TimeoutKind::Deadline));
+    assert!(sbox.tasks().is_empty());
+}
+
+#[test]
+fn grace_period_before_kill() {
+    // Ignores the grace signal, so only the kill after it ends the child
+    let argv = ["/bin/sh", "-c", "trap '' TERM; sleep 60"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_deadline(Some(Duration::milliseconds(100)));
+    sbox.set_grace_period(Some((Signal::Term, Duration::milliseconds(100))));
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(timed_out(&*seen.borrow(), TimeoutKind::Deadline));
+    assert!(killed(&*seen.borrow()));
+}
+
+#[test]
+fn try_tick_without_blocking() {
+    let argv = ["/bin/sleep", "0.2"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_event_fd(true);
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    assert!(sbox.event_fd().is_some());
+
+    let mut idle = false;
+    while sbox.is_running() {
+        match sbox.try_tick().ok().expect("Could not check for sandbox events") {
+            Some(_) => {},
+            None => {
+                idle = true;
+                timer::sleep(Duration::milliseconds(10));
+            }
+        }
+    }
+    assert!(idle);
+    match seen.borrow().last() {
+        Some(&State::Exit(0)) => {},
+        other => panic!("Expected the child to exit, got {:?}", other)
+    }
+    assert!(sbox.try_tick().ok().expect("Could not check for sandbox events").is_none());
+}
+
+#[test]
+fn no_event_fd_by_default() {
+    let argv = ["/bin/true"];
+    let (mut sbox, _) = sandbox(&argv);
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    assert!(sbox.event_fd().is_none());
+    run(&mut sbox);
+}
+
+fn exceeded(seen: &Vec<State>, limit: Limit) -> bool {
+    seen.iter().any(|s| match *s {
+        State::LimitExceeded(l) => l == limit,
+        _ => false
+    })
+}
+
+#[test]
+fn file_size_limit_enforced() {
+    let dir = TempDir::new("limits").unwrap();
+    let cmd = format!("exec head -c 4096 /dev/zero > {}", dir.path().join("big").display());
+    let argv = ["/bin/sh", "-c", &cmd[]];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_limits(Limits {file_size: Some(1024), ..Default::default()});
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(exceeded(&*seen.borrow(), Limit::FileSize));
+}
+
+#[test]
+fn cpu_limit_enforced() {
+    let argv = ["/bin/sh", "-c", "while :; do :; done"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.set_limits(Limits {cpu_seconds: Some(1), ..Default::default()});
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(exceeded(&*seen.borrow(), Limit::Cpu));
+}
+
+#[test]
+fn sent_limit_signal_is_not_a_limit() {
+    let argv = ["/bin/sh", "-c", "kill -XCPU $$"];
+    let (mut sbox, seen) = sandbox(&argv);
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(!exceeded(&*seen.borrow(), Limit::Cpu));
+    assert!(seen.borrow().iter().any(|s| match *s {
+        State::Signal(Signal::Xcpu) => true,
+        _ => false
+    }));
+}
+
+#[test]
+fn unapplied_limits_fail_spawn() {
+    // Far past the most open files the kernel allows anyone
+    let argv = ["/bin/true"];
+    let (mut sbox, _) = sandbox(&argv);
+    sbox.set_limits(Limits {open_files: Some(1 << 40), ..Default::default()});
+    match sbox.spawn() {
+        Err(SandboxError::Limits(_)) => {},
+        other => panic!("Expected the limits to fail, got {:?}", other)
+    }
+    assert!(!sbox.is_running());
+}
+
+#[test]
+fn tick_after_exit() {
+    let argv = ["/bin/true"];
+    let (mut sbox, _) = sandbox(&argv);
+    // Before spawning, and again once the child has gone, there is nothing
+    // to wait for
+    assert!(sbox.tick().is_ok());
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(sbox.tick().is_ok());
+    assert!(sbox.try_tick().ok().expect("try_tick failed").is_none());
+    sbox.set_deadline(Some(Duration::milliseconds(100)));
+    assert!(sbox.tick().is_ok());
+}
+
+#[test]
+fn denied_call_returns_errno() {
+    extern "C" { fn access(path: *const libc::c_char, mode: libc::c_int) -> libc::c_int; }
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    let watcher = events::ClosureWatcher::new(Box::new(move |event: &events::Event| {
+        match event.state {
+            // Denied tasks wait for the watcher like any other stop
+            State::Denied(_) | State::PTrace(_) | State::Signal(_) => {
+                let _ = event.cont();
+            },
+            _ => {}
+        }
+        log.borrow_mut().push(event.state);
+    }));
+    let mut policy = Policy::new();
+    policy.set_default(Action::Errno(libc::EXDEV as u32));
+    policy.allow(seccomp::Syscall::EXIT);
+    policy.allow(seccomp::Syscall::EXIT_GROUP);
+    let exec = sandbox::executors::Function::new(Box::new(move |&:| -> i32 {
+        let r = unsafe { access(b"/\0".as_ptr() as *const libc::c_char, 0) };
+        if r < 0 && os::errno() == libc::EXDEV as usize { 0 } else { 1 }
+    }));
+    let mut sbox = Sandbox::new(Box::new(exec), Box::new(watcher), policy);
+    sbox.spawn().ok().expect("Could not spawn sandbox");
+    run(&mut sbox);
+    assert!(seen.borrow().iter().any(|s| match *s {
+        State::Denied(_) => true,
+        _ => false
+    }));
+    match seen.borrow().last() {
+        Some(&State::Exit(0)) => {},
+        other => panic!("Expected access to fail with EXDEV, got {:?}", other)
+    }
+}
@@ -0,0 +1,131 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+extern crate libc;
+
+use sandbox::vfs;
+use sandbox::vfs::{Filesystem, FsRef, errno};
+use sandbox::vfs::tar::TarFS;
+use sandbox::io::Handle;
+use std::iter::repeat;
+use std::io::{FileType, SeekStyle};
+use std::i64;
+use common::{to_ref, open, list};
+
+mod common;
+
+fn octal(field: &mut [u8], value: u64) {
+    let s = format!("{:01$o}", value, field.len() - 1);
+    for (i, b) in s.bytes().enumerate() {
+        field[i] = b;
+    }
+}
+
+fn entry(archive: &mut Vec<u8>, name: &str, typeflag: u8, link: &str, data: &[u8]) {
+    let mut header: Vec<u8> = repeat(0u8).take(512).collect();
+    for (i, b) in name.bytes().enumerate() {
+        header[i] = b;
+    }
+    for (i, b) in link.bytes().enumerate() {
+        header[157 + i] = b;
+    }
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[124..136], data.len() as u64);
+    octal(&mut header[136..148], 0);
+    header[156] = typeflag;
+    for (i, b) in b"ustar\x0000".iter().enumerate() {
+        header[257 + i] = *b;
+    }
+    let sum = header.iter().fold(0u64, |s, b| s + *b as u64) + 8 * b' ' as u64;
+    octal(&mut header[148..155], sum);
+
+    archive.push_all(&header[]);
+    archive.push_all(data);
+    let pad = (512 - data.len() % 512) % 512;
+    archive.extend(repeat(0u8).take(pad));
+}
+
+fn bundle() -> FsRef<'static> {
+    let mut archive = Vec::new();
+    entry(&mut archive, "./app/", b'5', "", b"");
+    entry(&mut archive, "./app/main.js", b'0', "", b"console.log('hi')");
+    entry(&mut archive, "./app/index.js", b'2', "main.js", b"");
+    entry(&mut archive, "./lib/deep/util.js", b'0', "", b"util");
+    archive.extend(repeat(0u8).take(1024));
+    let fs = TarFS::new(archive).ok().expect("archive didn't index");
+    to_ref(fs)
+}
+
+#[test]
+fn read_file_contents() {
+    let fs = bundle();
+    let mut h = open(&fs, "/app/main.js", vfs::ReadOnly, 0);
+    let mut buf = [0u8; 64];
+    assert!(h.read(&mut buf) == Ok(17));
+    assert!(&buf[..17] == b"console.log('hi')");
+    assert!(h.read(&mut buf) == Ok(0));
+    assert!(h.stat().unwrap().size == 17);
+}
+
+#[test]
+fn implicit_directories_and_links() {
+    let fs = bundle();
+    assert!(fs.borrow().do_stat("/lib/deep").unwrap().kind == FileType::Directory);
+    assert!(fs.borrow().do_readlink("/app/index.js").unwrap() == "main.js");
+
+    assert!(list(&fs, "/") == vec![String::from_str("app"), String::from_str("lib")]);
+}
+
+#[test]
+fn archive_is_read_only() {
+    let fs = bundle();
+    let err = fs.borrow_mut().do_open("/app/main.js", vfs::WriteOnly, 0).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EROFS);
+    let err = fs.borrow_mut().do_open("/new", vfs::Create | vfs::WriteOnly, 0o644).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EROFS);
+}
+
+#[test]
+fn huge_size_rejected() {
+    let mut archive = Vec::new();
+    entry(&mut archive, "./big", b'0', "", b"");
+    // A base-256 size that wraps the data offset around to the start
+    archive[124] = 0x80;
+    for b in archive[125..128].iter_mut() {
+        *b = 0;
+    }
+    for b in archive[128..136].iter_mut() {
+        *b = 0xff;
+    }
+    for b in archive[148..156].iter_mut() {
+        *b = 0;
+    }
+    let sum = archive[..512].iter().fold(0u64, |s, b| s + *b as u64) + 8 * b' ' as u64;
+    octal(&mut archive[148..155], sum);
+    archive.extend(repeat(0u8).take(1024));
+    assert!(TarFS::new(archive).is_err());
+}
+
+#[test]
+fn file_named_root_rejected() {
+    let mut archive = Vec::new();
+    entry(&mut archive, "./", b'0', "", b"data");
+    archive.extend(repeat(0u8).take(1024));
+    assert!(TarFS::new(archive).is_err());
+
+    let mut archive = Vec::new();
+    entry(&mut archive, "./", b'5', "", b"");
+    archive.extend(repeat(0u8).take(1024));
+    let fs = TarFS::new(archive).ok().expect("archive didn't index");
+    assert!(fs.do_stat("/").unwrap().kind == FileType::Directory);
+}
+
+#[test]
+fn seek_bounds() {
+    let fs = bundle();
+    let mut h = open(&fs, "/app/main.js", vfs::ReadOnly, 0);
+    let err = h.seek(-18, SeekStyle::SeekEnd).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EINVAL);
+    assert!(h.seek(i64::MAX, SeekStyle::SeekSet) == Ok(i64::MAX as u64));
+    let err = h.seek(1, SeekStyle::SeekCur).unwrap_err();
+    assert!(errno::from_io_error(&err) == libc::EOVERFLOW);
+}
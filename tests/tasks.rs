@@ -0,0 +1,52 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+
+use sandbox::tasks::{TaskTable, Kind};
+
+#[test]
+fn track_children() {
+    let mut t = TaskTable::new();
+    t.insert_root(100);
+    t.insert(101, 100, Kind::Thread);
+    t.insert(102, 100, Kind::Process);
+    t.insert(103, 102, Kind::Process);
+    assert!(t.len() == 4);
+    let mut children = t.children(100);
+    children.sort();
+    assert!(children == vec![101, 102]);
+    assert!(t.get(103).unwrap().parent == 102);
+}
+
+#[test]
+fn start_new_task() {
+    let mut t = TaskTable::new();
+    t.insert_root(100);
+    t.insert(101, 100, Kind::Thread);
+    assert!(!t.start(100));
+    assert!(t.start(101));
+    assert!(!t.start(101));
+    assert!(t.get(101).unwrap().is_started());
+}
+
+#[test]
+fn remove_all() {
+    let mut t = TaskTable::new();
+    t.insert_root(100);
+    t.insert(101, 100, Kind::Thread);
+    assert!(t.remove(101).is_some());
+    assert!(!t.is_empty());
+    assert!(t.remove(100).is_some());
+    assert!(t.is_empty());
+}
+
+#[test]
+fn process_of_thread() {
+    let mut t = TaskTable::new();
+    t.insert_root(100);
+    t.insert(101, 100, Kind::Thread);
+    t.insert(102, 101, Kind::Thread);
+    t.insert(103, 102, Kind::Process);
+    assert!(t.process_of(102) == 100);
+    assert!(t.process_of(103) == 103);
+    assert!(t.process_of(999) == 999);
+}
@@ -0,0 +1,184 @@
+#![allow(unstable)]
+extern crate "codius-sandbox-core" as sandbox;
+
+use sandbox::vfs::{FdAllocator, FIRST_VIRTUAL_FD, Stat, synthetic_stat};
+use std::mem;
+use std::io::FileType;
+
+#[test]
+fn allocate_from_threshold() {
+    let mut fds = FdAllocator::new();
+    assert!(fds.allocate() == FIRST_VIRTUAL_FD);
+    assert!(fds.allocate() == FIRST_VIRTUAL_FD + 1);
+}
+
+#[test]
+fn reuse_lowest_closed_fd() {
+    let mut fds = FdAllocator::new();
+    let a = fds.allocate();
+    let b = fds.allocate();
+    let c = fds.allocate();
+    fds.release(c);
+    fds.release(a);
+    assert!(fds.allocate() == a);
+    assert!(fds.allocate() == c);
+    assert!(fds.allocate() == c + 1);
+    fds.release(b);
+    assert!(fds.allocate() == b);
+}
+
+#[test]
+fn ignore_foreign_fds() {
+    let mut fds = FdAllocator::new();
+    fds.release(1);
+    fds.release(FIRST_VIRTUAL_FD + 10);
+    assert!(fds.allocate() == FIRST_VIRTUAL_FD);
+}
+
+#[test]
+fn normalize_paths() {
+    use sandbox::vfs::path::normalize;
+    assert!(normalize("/", "/app/../etc/passwd") == "/etc/passwd");
+    assert!(normalize("/", "//app//./lib/") == "/app/lib");
+    assert!(normalize("/", "/../../etc") == "/etc");
+    assert!(normalize("/app", "lib/../index.js") == "/app/index.js");
+    assert!(normalize("/app", "../../..") == "/");
+    assert!(normalize("/app", "./") == "/app");
+}
+
+#[test]
+fn open_flags_access_mode() {
+    use sandbox::vfs::OpenFlags;
+    let ro = OpenFlags::from_bits_truncate(0);
+    assert!(ro.is_readable() && !ro.is_writable());
+    let wo = OpenFlags::from_bits_truncate(0o1 | 0o100);
+    assert!(!wo.is_readable() && wo.is_writable());
+    assert!(wo.contains(sandbox::vfs::Create));
+    let rw = OpenFlags::from_bits_truncate(0o2 | 0o2000);
+    assert!(rw.is_readable() && rw.is_writable());
+    assert!(rw.contains(sandbox::vfs::Append));
+}
+
+#[test]
+fn set_cwd() {
+    let mut v = sandbox::vfs::VFS::new();
+    assert!(v.get_cwd() == "/");
+    v.set_cwd("/app/./lib/..");
+    assert!(v.get_cwd() == "/app");
+}
+
+#[test]
+fn errno_round_trip() {
+    use sandbox::vfs::errno;
+    // ENOENT, ENOTDIR, EISDIR and EDQUOT on Linux
+    for e in [2, 20, 21, 122].iter() {
+        assert!(errno::from_io_error(&errno::error(*e)) == *e);
+    }
+}
+
+#[test]
+fn errno_from_host_error() {
+    use sandbox::vfs::errno;
+    use std::io::{IoError, IoErrorKind};
+    let err = IoError {
+        kind: IoErrorKind::OtherIoError,
+        desc: "unknown error",
+        detail: Some(String::from_str("not a directory"))
+    };
+    assert!(errno::from_io_error(&err) == 20);
+    // ENOTEMPTY and ENFILE, with the path std appends
+    let err = IoError {
+        kind: IoErrorKind::OtherIoError,
+        desc: "couldn't remove directory",
+        detail: Some(String::from_str("directory not empty; path=/tmp/a"))
+    };
+    assert!(errno::from_io_error(&err) == 39);
+    let err = IoError {
+        kind: IoErrorKind::ResourceUnavailable,
+        desc: "couldn't open path as file",
+        detail: Some(String::from_str("too many open files in system; path=/tmp/a"))
+    };
+    assert!(errno::from_io_error(&err) == 23);
+    let err = IoError {kind: IoErrorKind::FileNotFound, desc: "couldn't open file", detail: None};
+    assert!(errno::from_io_error(&err) == 2);
+}
+
+#[test]
+fn errno_from_failed_host_call() {
+    use sandbox::vfs::errno;
+    use std::io::{File, TempDir, FilePermission};
+    use std::io::fs;
+    // ENOENT, EEXIST, ENOTDIR and ENOTEMPTY on Linux
+    let dir = TempDir::new("errno").unwrap();
+    let err = File::open(&dir.path().join("missing")).unwrap_err();
+    assert!(errno::from_io_error(&err) == 2);
+    let err = fs::mkdir(dir.path(), FilePermission::from_bits_truncate(0o755)).unwrap_err();
+    assert!(errno::from_io_error(&err) == 17);
+    File::create(&dir.path().join("file")).unwrap();
+    let err = fs::readdir(&dir.path().join("file")).unwrap_err();
+    assert!(errno::from_io_error(&err) == 20);
+    let err = fs::rmdir(dir.path()).unwrap_err();
+    assert!(errno::from_io_error(&err) == 39);
+}
+
+#[test]
+fn quota_limits() {
+    use sandbox::vfs::{errno, Quota, Usage};
+    let quota = Quota {
+        max_bytes: Some(10),
+        max_inodes: Some(1),
+        max_open_files: Some(2)
+    };
+    let mut usage: Usage = Default::default();
+    assert!(quota.check_bytes(&usage, 10).is_ok());
+    usage.resize(0, 8);
+    // EDQUOT, ENOSPC and EMFILE on Linux
+    assert!(errno::from_io_error(&quota.check_bytes(&usage, 3).unwrap_err()) == 122);
+    usage.add_inode();
+    assert!(errno::from_io_error(&quota.check_inodes(&usage).unwrap_err()) == 28);
+    assert!(quota.check_open_files(1).is_ok());
+    assert!(errno::from_io_error(&quota.check_open_files(2).unwrap_err()) == 24);
+
+    usage.resize(8, 2);
+    assert!(usage.bytes == 2);
+    usage.free_bytes(5);
+    assert!(usage.bytes == 0);
+}
+
+#[test]
+fn unlimited_by_default() {
+    let v = sandbox::vfs::VFS::new();
+    assert!(v.get_quota() == Default::default());
+    assert!(v.get_usage() == Default::default());
+    assert!(v.get_quota().check_bytes(&v.get_usage(), 1 << 40).is_ok());
+}
+
+#[test]
+fn transfers_clamped() {
+    use sandbox::vfs::transfer_len;
+    use sandbox::events::MAX_TRANSFER;
+    assert!(transfer_len(10) == 10);
+    assert!(transfer_len(MAX_TRANSFER as u64) == MAX_TRANSFER);
+    assert!(transfer_len(1 << 40) == MAX_TRANSFER);
+    assert!(transfer_len(!0) == MAX_TRANSFER);
+}
+
+#[test]
+fn iovecs_clamped() {
+    use sandbox::vfs::clamp_iovecs;
+    use sandbox::events::MAX_TRANSFER;
+    let small = vec![(0x1000, 4), (0x2000, 8)];
+    assert!(clamp_iovecs(&small[]) == small);
+    let big = vec![(0x1000, 16), (0x2000, MAX_TRANSFER), (0x3000, 4)];
+    assert!(clamp_iovecs(&big[]) == vec![(0x1000, 16), (0x2000, MAX_TRANSFER - 16)]);
+    assert!(clamp_iovecs(&[(0x1000, !0)]) == vec![(0x1000, MAX_TRANSFER)]);
+}
+
+#[test]
+fn stat_layout() {
+    // sizeof(struct stat) and offsetof(struct stat, st_size) on x86_64
+    assert!(mem::size_of::<Stat>() == 144);
+    let stat = Stat::from_file_stat(&synthetic_stat(FileType::RegularFile, 0o644, 1234, 7));
+    let size = unsafe { *((&stat as *const Stat as *const u8).offset(48) as *const i64) };
+    assert!(size == 1234);
+}